@@ -1,22 +1,47 @@
 use crate::material::hittable::Hittable;
-use crate::material::{ScatterRecord, hittable};
-use crate::pdf::{HittablePdf, MixturePdf, Pdf};
 use crate::rtweekend::color::Color;
-use crate::rtweekend::interval::Interval;
+use crate::rtweekend::sampler::{self, Dimension, Sampler, SamplerKind};
 use crate::rtweekend::vec3::ray::Ray;
-use crate::rtweekend::vec3::{Point3, Vec3, random_in_unit_disk, unit_vector};
-use crate::rtweekend::{color, degrees_to_radians, random_double, vec3};
-use console::style;
-use image::{ImageBuffer, RgbImage};
-use indicatif::ProgressBar;
+use crate::rtweekend::vec3::{Point3, Vec3, sample_unit_disk, unit_vector};
+use crate::rtweekend::{degrees_to_radians, random_double, vec3};
+use aov::{AovSamples, MaterialIds};
+use checkpoint::Checkpoint;
+use filter::{Filter, FilterKind};
+use framebuffer::{Framebuffer, PixelSamples, SplatBuffer};
+use indicatif::{ProgressBar, ProgressStyle};
+use integrator::debug::{AmbientOcclusion, DepthView, MaterialIdView, NormalsView, UvView};
+use integrator::path::PathTracer;
+use integrator::{Integrator, IntegratorKind};
+use preview::Preview;
 use rayon::prelude::*;
-use std::sync::Arc;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub(crate) mod aov;
+pub(crate) mod checkpoint;
+mod denoise;
+pub(crate) mod filter;
+pub(crate) mod framebuffer;
+pub(crate) mod integrator;
+pub(crate) mod preview;
+mod tile;
+
+// Samples per pass are PASS_SQRT_SPP squared when rendering progressively.
+const PASS_SQRT_SPP: i32 = 4;
+// Passes taken before a pixel may be declared converged, so its variance estimate is usable.
+const MIN_ADAPTIVE_PASSES: u32 = 2;
 
 pub(crate) struct Camera {
     pub aspect_ratio: f64,      //default in 1.0
     pub image_width: u32,       //default in 100
     pub samples_per_pixel: u32, //default in 10
     pub max_depth: i32,         // default in 10
+    pub max_diffuse_depth: Option<i32>,
+    pub max_specular_depth: Option<i32>,
+    pub max_transmission_depth: Option<i32>,
+    pub russian_roulette_depth: Option<i32>, // bounces before paths may be ended by Russian roulette
     pub background: Color,
 
     pub vfov: f64, // Vertical view angle (field of view)
@@ -27,10 +52,24 @@ pub(crate) struct Camera {
     pub defocus_angle: f64,
     pub focus_dist: f64,
 
+    pub adaptive_threshold: f64, // relative error at which a pixel stops sampling, 0 disables
+    pub time_budget: Option<Duration>, // keep adding passes until this much time has passed
+    pub checkpoint: Option<Checkpoint>, // save progress to resume the render later
+    pub integrator: IntegratorKind,
+    pub ao_radius: f64, // distance within which geometry occludes in the ao view
+    pub sampler: SamplerKind,
+    pub filter: FilterKind,
+    pub filter_radius: Option<f64>, // defaults to the radius suited to the filter
+    pub preview: Option<Preview>,   // image of the render in progress
+    pub aovs: bool,                 // record first-hit albedo, normal, position, depth and ids
+    pub denoise: bool,              // filter the noise out of the finished render
+
     image_height: u32,
-    pixel_samples_scale: f64,
     sqrt_spp: i32,
-    recip_sqrt_spp: f64,
+    // Built from the public settings by initialize.
+    pixel_filter: Arc<dyn Filter>,
+    tracer: Option<Arc<dyn Integrator>>,
+    material_ids: Arc<MaterialIds>, // numbered by render from the world it is given
     center: Point3,
     pixel00_loc: Point3,
     pixel_delta_u: Vec3,
@@ -49,6 +88,10 @@ impl Camera {
             image_width: 100,
             samples_per_pixel: 10,
             max_depth: 10,
+            max_diffuse_depth: None,
+            max_specular_depth: None,
+            max_transmission_depth: None,
+            russian_roulette_depth: None,
             background: Color::new(0.0, 0.0, 0.0),
 
             vfov: 90.0,
@@ -59,10 +102,23 @@ impl Camera {
             defocus_angle: 0.0,
             focus_dist: 1.0,
 
+            adaptive_threshold: 0.0,
+            time_budget: None,
+            checkpoint: None,
+            integrator: IntegratorKind::Mixture,
+            ao_radius: 100.0,
+            sampler: SamplerKind::Stratified,
+            filter: FilterKind::Box,
+            filter_radius: None,
+            preview: None,
+            aovs: false,
+            denoise: false,
+
             image_height: 0,
-            pixel_samples_scale: 0.0,
             sqrt_spp: 1,
-            recip_sqrt_spp: 1.0,
+            pixel_filter: FilterKind::Box.build(0.5),
+            tracer: None,
+            material_ids: Arc::default(),
             center: Point3::new(0.0, 0.0, 0.0),
             pixel00_loc: Point3::new(0.0, 0.0, 0.0),
             pixel_delta_u: Vec3::new(0.0, 0.0, 0.0),
@@ -86,8 +142,12 @@ impl Camera {
         };
 
         self.sqrt_spp = (self.samples_per_pixel as f64).sqrt() as i32;
-        self.pixel_samples_scale = 1.0 / (self.sqrt_spp * self.sqrt_spp) as f64;
-        self.recip_sqrt_spp = 1.0 / self.sqrt_spp as f64;
+        self.pixel_filter = self.filter.build(
+            self.filter_radius
+                .unwrap_or_else(|| self.filter.default_radius()),
+        );
+
+        self.tracer = Some(self.build_integrator());
 
         self.center = self.lookfrom;
 
@@ -115,127 +175,347 @@ impl Camera {
         self.defocus_disk_v = self.v * defocus_radius;
     }
 
-    fn sample_square_stratified(&self, s_i: u32, s_j: u32) -> Vec3 {
-        let px = ((s_i as f64 + random_double()) * self.recip_sqrt_spp) - 0.5;
-        let py = ((s_j as f64 + random_double()) * self.recip_sqrt_spp) - 0.5;
-
-        Vec3::new(px, py, 0.0)
+    fn build_integrator(&self) -> Arc<dyn Integrator> {
+        let path_tracer = |mis| {
+            Arc::new(PathTracer {
+                mis,
+                background: self.background,
+                max_depth: self.max_depth,
+                max_diffuse_depth: self.max_diffuse_depth,
+                max_specular_depth: self.max_specular_depth,
+                max_transmission_depth: self.max_transmission_depth,
+                russian_roulette_depth: self.russian_roulette_depth,
+            })
+        };
+        match self.integrator {
+            IntegratorKind::Mixture => path_tracer(false),
+            IntegratorKind::Mis => path_tracer(true),
+            IntegratorKind::Normals => Arc::new(NormalsView),
+            IntegratorKind::Uv => Arc::new(UvView),
+            IntegratorKind::Depth => Arc::new(DepthView {
+                scale: 0.5 / (self.lookfrom - self.lookat).length(),
+            }),
+            IntegratorKind::AmbientOcclusion => Arc::new(AmbientOcclusion {
+                radius: self.ao_radius,
+            }),
+            IntegratorKind::MaterialId => Arc::new(MaterialIdView {
+                material_ids: self.material_ids.clone(),
+            }),
+        }
     }
+
     fn sample_square(&self) -> Vec3 {
         Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
     }
 
     fn defocus_disk_sample(&self) -> Point3 {
-        let p = random_in_unit_disk();
+        let (u, v) = sampler::sample_2d(Dimension::Lens);
+        let p = sample_unit_disk(u, v);
         self.center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
     }
 
-    fn get_ray(&self, i: u32, j: u32, s_i: u32, s_j: u32) -> Ray {
-        let offset = self.sample_square_stratified(s_i, s_j);
+    // (px, py) is the position of the sample in the pixel footprint, in [0,1).
+    fn get_ray(&self, i: u32, j: u32, px: f64, py: f64) -> Ray {
         let pixel_sample = self.pixel00_loc
-            + ((i as f64 + offset.x) * self.pixel_delta_u)
-            + ((j as f64 + offset.y) * self.pixel_delta_v);
+            + ((i as f64 + px - 0.5) * self.pixel_delta_u)
+            + ((j as f64 + py - 0.5) * self.pixel_delta_v);
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.center
         } else {
             self.defocus_disk_sample()
         };
         let ray_direction = pixel_sample - ray_origin;
-        let ray_time = random_double();
+        let ray_time = sampler::sample_1d(Dimension::Time);
 
         Ray::new_move(ray_origin, ray_direction, ray_time)
     }
 
-    fn ray_color(
+    // Takes sqrt_n * sqrt_n samples, numbered from first_sample on, and splats them into the
+    // neighbouring pixels as well.
+    #[allow(clippy::too_many_arguments)]
+    fn sample_pixel(
         &self,
-        r: &Ray,
-        depth: i32,
+        i: u32,
+        j: u32,
+        first_sample: u32,
+        sqrt_n: i32,
+        sampler: &Arc<dyn Sampler>,
+        splats: &mut SplatBuffer,
         world: &dyn Hittable,
         lights: Arc<dyn Hittable>,
-    ) -> Color {
-        if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
-        }
-
-        let mut rec: hittable::HitRecord = hittable::HitRecord::new();
-        if !world.hit(&r, &mut Interval::new(0.001, f64::INFINITY), &mut rec) {
-            return self.background.clone();
-        }
-
-        let mut srec = ScatterRecord::default();
-        let color_from_emission = rec.mat.emitted(r, &rec.clone(), rec.u, rec.v, &rec.p);
-
-        if !rec.mat.scatter(r, &rec, &mut srec) {
-            return color_from_emission;
-        }
-
-        if srec.skip_pdf {
-            return color_from_emission
-                + srec.attenuation * self.ray_color(&srec.skip_pdf_ray, depth - 1, world, lights);
+    ) -> (PixelSamples, AovSamples) {
+        let tracer = self
+            .tracer
+            .as_ref()
+            .expect("render builds the integrator first");
+        let mut samples = PixelSamples::default();
+        let mut aovs = AovSamples::default();
+        for s in 0..(sqrt_n * sqrt_n) as u32 {
+            sampler::start_sample(sampler, (i, j), first_sample + s);
+            let (px, py) = sampler::sample_2d(Dimension::Pixel);
+            let r = self.get_ray(i, j, px, py);
+            let (color, first_hit) = tracer.ray_color(&r, world, &lights);
+            let hit = first_hit.is_some();
+            samples.add(color, hit);
+            splats.splat(
+                self.pixel_filter.as_ref(),
+                i as f64 + px,
+                j as f64 + py,
+                color,
+                hit,
+            );
+            if self.aovs || self.denoise {
+                if let Some(rec) = &first_hit {
+                    aovs.add(&aov::from_hit(&r, rec, &self.material_ids));
+                }
+            }
         }
+        (samples, aovs)
+    }
 
-        let light_ptr = Arc::new(HittablePdf::new(lights.clone(), rec.p));
-        let p = MixturePdf::new(light_ptr, srec.pdf_ptr.clone());
-
-        let scattered = Ray::new_move(rec.p, p.generate(), r.time);
-        let pdf_value = p.value(&scattered.direction);
-
-        let scattering_pdf = rec.mat.scattering_pdf(r, &rec, &scattered);
-
-        let sample_color = self.ray_color(&scattered, depth - 1, world, lights.clone());
-        let color_from_scatter =
-            (srec.attenuation.clone() * scattering_pdf * sample_color) / pdf_value;
-
-        color_from_emission + color_from_scatter
+    // Everything that changes the image except the sample count, see Checkpoint.
+    fn fingerprint(&self, checkpoint: &Checkpoint) -> u64 {
+        checkpoint::fingerprint(&format!(
+            "{}|{:?}|{}x{}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}",
+            checkpoint.scene,
+            checkpoint.seed,
+            self.image_width,
+            self.image_height,
+            self.integrator,
+            self.sampler,
+            self.filter,
+            self.pixel_filter.radius(),
+            self.max_depth,
+            self.max_diffuse_depth,
+            self.max_specular_depth,
+            self.max_transmission_depth,
+            self.russian_roulette_depth,
+            self.ao_radius,
+            self.background,
+            self.vfov,
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.defocus_angle,
+            self.focus_dist,
+        ))
     }
 
-    pub fn render(&mut self, world: &dyn Hittable, lights: Arc<dyn Hittable>) {
+    pub fn render(
+        &mut self,
+        world: &dyn Hittable,
+        lights: Arc<dyn Hittable>,
+    ) -> io::Result<Framebuffer> {
+        self.material_ids = Arc::new(MaterialIds::new(world));
         self.initialize();
+        let start = Instant::now();
+
+        // Without adaptive sampling, a time budget or checkpoints the whole pixel budget is
+        // taken in one pass. Otherwise samples are added in small passes, and between passes the
+        // pixels whose estimate has converged drop out, so later passes only go to the noisy
+        // pixels.
+        let adaptive = self.adaptive_threshold > 0.0;
+        let progressive = adaptive || self.time_budget.is_some() || self.checkpoint.is_some();
+        let pass_sqrt = if progressive {
+            PASS_SQRT_SPP.min(self.sqrt_spp)
+        } else {
+            self.sqrt_spp
+        };
+        let pass_spp = pass_sqrt * pass_sqrt;
+        let max_passes = ((self.sqrt_spp * self.sqrt_spp + pass_spp - 1) / pass_spp) as u32;
+
+        let fingerprint = self
+            .checkpoint
+            .as_ref()
+            .map_or(0, |checkpoint| self.fingerprint(checkpoint));
+        let resumed = match &self.checkpoint {
+            Some(checkpoint) => {
+                checkpoint.load(fingerprint, self.image_width, self.image_height)?
+            }
+            None => None,
+        };
+        let (mut fb, mut pass) = match resumed {
+            Some((fb, pass)) => {
+                println!("Resuming the render after {} passes", pass);
+                (fb, pass)
+            }
+            None => (Framebuffer::new(self.image_width, self.image_height), 0),
+        };
+        // The denoiser is guided by the AOVs.
+        fb.set_aovs(self.aovs || self.denoise);
+
+        // Tiles keep the pixels still taking samples, empty tiles are dropped.
+        let mut tiles = tile::spiral_tiles(self.image_width, self.image_height);
+        for tile in &mut tiles {
+            tile.retain(|&(i, j)| {
+                !adaptive
+                    || pass < MIN_ADAPTIVE_PASSES
+                    || fb.samples(i, j).relative_error() > self.adaptive_threshold
+            });
+        }
+        tiles.retain(|tile| !tile.is_empty());
+        let active_pixels = |tiles: &[Vec<(u32, u32)>]| tiles.iter().map(Vec::len).sum::<usize>();
 
-        let path = std::path::Path::new("output/Try/image12.png");
-        let prefix = path.parent().unwrap();
-        std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-
-        // different from the book, we use image crate to create a .png image rather than outputting .ppm file, which is not widely used.
-        // anyway, you may output any image format you like.
-        let mut img: RgbImage = ImageBuffer::new(self.image_width, self.image_height);
-
+        let pixels = (self.image_height * self.image_width) as u64;
         let progress = if option_env!("CI").unwrap_or_default() == "true" {
             ProgressBar::hidden()
+        } else if let Some(budget) = self.time_budget {
+            ProgressBar::new(budget.as_millis() as u64)
         } else {
-            ProgressBar::new((self.image_height * self.image_width) as u64)
+            let progress = ProgressBar::new(pixels * max_passes as u64);
+            let remaining = max_passes.saturating_sub(pass) as u64;
+            progress.set_position(
+                pixels * max_passes as u64 - active_pixels(&tiles) as u64 * remaining,
+            );
+            progress
         };
+        progress.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {wide_bar:.cyan/blue} {percent:>3}% ETA {eta_precise}",
+            )
+            .unwrap(),
+        );
 
-        let pixels: Vec<_> = (0..self.image_height)
-            .rev()
-            .flat_map(|j| (0..self.image_width).map(move |i| (i, j)))
-            .collect();
-
-        let colors: Vec<Color> = pixels
-            .par_iter() // 现在可以正确调用
-            .map(|&(i, j)| {
-                let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                for s_j in 0..self.sqrt_spp {
-                    for s_i in 0..self.sqrt_spp {
-                        let r = self.get_ray(i, j, s_i as u32, s_j as u32);
-                        pixel_color += self.ray_color(&r, self.max_depth, world, lights.clone());
+        let sampler = self.sampler.build(pass_sqrt as u32);
+        let fb = Mutex::new(fb);
+        let last_preview = Mutex::new(Instant::now());
+        let mut last_save = Instant::now();
+        while !tiles.is_empty() && (self.time_budget.is_some() || pass < max_passes) {
+            // Every thread takes the next unrendered tile, so the tiles finish in spiral order.
+            let next = AtomicUsize::new(0);
+            // Splats reach into the neighbouring tiles. They are merged in tile order after the
+            // pass, so the sums don't depend on which thread finished first.
+            let finished: Vec<Mutex<Option<SplatBuffer>>> =
+                tiles.iter().map(|_| Mutex::new(None)).collect();
+            (0..rayon::current_num_threads())
+                .into_par_iter()
+                .try_for_each(|_| -> io::Result<()> {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(tile) = tiles.get(index) else {
+                            break;
+                        };
+                        // The samples a pixel already has number the new ones, which picks
+                        // their random sequences.
+                        let (counts, mut splats) = {
+                            let fb = fb.lock().unwrap();
+                            let counts: Vec<u32> = tile
+                                .iter()
+                                .map(|&(i, j)| fb.samples(i, j).count())
+                                .collect();
+                            let min = tile
+                                .iter()
+                                .fold((u32::MAX, u32::MAX), |(x, y), &(i, j)| (x.min(i), y.min(j)));
+                            let max = tile
+                                .iter()
+                                .fold((0, 0), |(x, y), &(i, j)| (x.max(i), y.max(j)));
+                            let splats =
+                                SplatBuffer::new(&fb, min, max, self.pixel_filter.radius());
+                            (counts, splats)
+                        };
+                        let samples: Vec<(PixelSamples, AovSamples)> = tile
+                            .iter()
+                            .zip(counts)
+                            .map(|(&(i, j), count)| {
+                                self.sample_pixel(
+                                    i,
+                                    j,
+                                    count,
+                                    pass_sqrt,
+                                    &sampler,
+                                    &mut splats,
+                                    world,
+                                    lights.clone(),
+                                )
+                            })
+                            .collect();
+
+                        let mut fb = fb.lock().unwrap();
+                        for (&(i, j), (samples, aovs)) in tile.iter().zip(&samples) {
+                            fb.add_samples(i, j, samples);
+                            fb.add_aovs(i, j, aovs);
+                        }
+                        *finished[index].lock().unwrap() = Some(splats);
+                        match self.time_budget {
+                            Some(budget) => progress
+                                .set_position(start.elapsed().min(budget).as_millis() as u64),
+                            None => progress.inc(tile.len() as u64),
+                        }
+
+                        let Some(preview) = &self.preview else {
+                            continue;
+                        };
+                        let img = {
+                            let mut last_preview = last_preview.lock().unwrap();
+                            if last_preview.elapsed() < preview.interval {
+                                continue;
+                            }
+                            *last_preview = Instant::now();
+                            preview.image(&fb)
+                        };
+                        drop(fb);
+                        preview.save(&img)?;
                     }
+                    Ok(())
+                })?;
+            pass += 1;
+
+            let mut fb = fb.lock().unwrap();
+            for splats in finished {
+                if let Some(splats) = splats.into_inner().unwrap() {
+                    fb.add_splats(&splats);
                 }
-                pixel_color * self.pixel_samples_scale
-            })
-            .collect();
+            }
+            let before = active_pixels(&tiles);
+            if adaptive && pass >= MIN_ADAPTIVE_PASSES {
+                for tile in &mut tiles {
+                    tile.retain(|&(i, j)| {
+                        fb.samples(i, j).relative_error() > self.adaptive_threshold
+                    });
+                }
+                tiles.retain(|tile| !tile.is_empty());
+            }
+
+            if let Some(checkpoint) = &self.checkpoint {
+                if last_save.elapsed() >= checkpoint.interval {
+                    checkpoint.save(fingerprint, &fb, pass)?;
+                    last_save = Instant::now();
+                }
+            }
 
-        for ((i, j), color) in pixels.into_iter().zip(colors) {
-            let pixel = img.get_pixel_mut(i, j);
-            color::write_color(pixel, &color);
-            progress.inc(1);
+            if let Some(budget) = self.time_budget {
+                if start.elapsed() >= budget {
+                    break;
+                }
+            } else {
+                // Converged pixels skip their remaining passes.
+                let remaining = max_passes.saturating_sub(pass) as u64;
+                progress.inc((before - active_pixels(&tiles)) as u64 * remaining);
+            }
         }
         progress.finish();
+        let mut fb = fb.into_inner().unwrap();
 
-        println!(
-            "Output image as \"{}\"",
-            style(path.to_str().unwrap()).yellow()
-        );
-        img.save(path).expect("Cannot save the image to the file");
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.save(fingerprint, &fb, pass)?;
+        }
+
+        if self.denoise {
+            let denoise_start = Instant::now();
+            let denoised = denoise::denoise(&fb);
+            fb.set_denoised(denoised);
+            fb.set_aovs(self.aovs);
+            println!("Denoised in {:.2}s", denoise_start.elapsed().as_secs_f64());
+        }
+
+        if progressive {
+            println!(
+                "{} passes, {:.1} samples per pixel on average",
+                pass,
+                fb.total_samples() as f64 / pixels as f64
+            );
+        }
+
+        Ok(fb)
     }
 }
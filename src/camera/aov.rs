@@ -0,0 +1,177 @@
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::material::{Material, ScatterRecord};
+use crate::rtweekend::color::Color;
+use crate::rtweekend::vec3::ray::Ray;
+use crate::rtweekend::vec3::{Point3, Vec3};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+// Arbitrary output variables: what the camera ray hit first, written next to the beauty image
+// for denoisers and compositing.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Aovs {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub position: Point3,
+    pub depth: f64,
+    pub object_id: u32, // index + 1 in the outermost object list, 0 where nothing was hit
+    pub material_id: u32,
+}
+
+// Numbers the materials of a scene from 1 in the order the world lists them, so a material
+// keeps its id from one run to the next. Materials are shared through Arcs, their addresses only
+// find the number during a render. Hits on anything unlisted get 0.
+#[derive(Default)]
+pub(crate) struct MaterialIds(HashMap<usize, u32>);
+
+impl MaterialIds {
+    pub(crate) fn new(world: &dyn Hittable) -> MaterialIds {
+        let mut ids = HashMap::new();
+        world.materials(&mut |mat| {
+            let next = ids.len() as u32 + 1;
+            ids.entry(address(mat)).or_insert(next);
+        });
+        MaterialIds(ids)
+    }
+
+    pub(crate) fn get(&self, mat: &Arc<dyn Material>) -> u32 {
+        self.0.get(&address(mat)).copied().unwrap_or(0)
+    }
+}
+
+fn address(mat: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(mat) as *const () as usize
+}
+
+// AOVs of the first hit `rec` of the camera ray `r`, as returned by the integrator.
+pub(crate) fn from_hit(r: &Ray, rec: &HitRecord, material_ids: &MaterialIds) -> Aovs {
+    // The albedo is the attenuation of the material, lights give their emission clamped to
+    // the displayable range instead.
+    let mut srec = ScatterRecord::default();
+    let albedo = if rec.mat.scatter(r, rec, &mut srec) {
+        srec.attenuation
+    } else {
+        let emitted = rec.mat.emitted(r, rec, rec.u, rec.v, &rec.p);
+        Color::new(
+            emitted.x.clamp(0.0, 1.0),
+            emitted.y.clamp(0.0, 1.0),
+            emitted.z.clamp(0.0, 1.0),
+        )
+    };
+
+    Aovs {
+        albedo,
+        normal: rec.normal,
+        position: rec.p,
+        depth: (rec.p - r.origin).length(),
+        object_id: rec.object_id,
+        material_id: material_ids.get(&rec.mat),
+    }
+}
+
+// AOVs of the samples of one pixel that hit geometry. Albedo, normal, position and depth are
+// averaged, ids can't be, so they come from the first of those samples.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct AovSamples {
+    sum: Aovs,
+    count: u32,
+}
+
+impl AovSamples {
+    pub(crate) fn add(&mut self, aovs: &Aovs) {
+        if self.count == 0 {
+            self.sum.object_id = aovs.object_id;
+            self.sum.material_id = aovs.material_id;
+        }
+        self.sum.albedo += aovs.albedo;
+        self.sum.normal += aovs.normal;
+        self.sum.position += aovs.position;
+        self.sum.depth += aovs.depth;
+        self.count += 1;
+    }
+
+    pub(crate) fn merge(&mut self, other: &AovSamples) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            self.sum.object_id = other.sum.object_id;
+            self.sum.material_id = other.sum.material_id;
+        }
+        self.sum.albedo += other.sum.albedo;
+        self.sum.normal += other.sum.normal;
+        self.sum.position += other.sum.position;
+        self.sum.depth += other.sum.depth;
+        self.count += other.count;
+    }
+
+    pub(crate) fn mean(&self) -> Aovs {
+        if self.count == 0 {
+            return Aovs::default();
+        }
+        let n = self.count as f64;
+        let normal = self.sum.normal / n;
+        Aovs {
+            albedo: self.sum.albedo / n,
+            // Averaged unit vectors get shorter at edges, renormalize them.
+            normal: if normal.length() > 0.0 {
+                normal / normal.length()
+            } else {
+                normal
+            },
+            position: self.sum.position / n,
+            depth: self.sum.depth / n,
+            object_id: self.sum.object_id,
+            material_id: self.sum.material_id,
+        }
+    }
+
+    pub(crate) fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let s = &self.sum;
+        for x in [
+            s.albedo.x,
+            s.albedo.y,
+            s.albedo.z,
+            s.normal.x,
+            s.normal.y,
+            s.normal.z,
+            s.position.x,
+            s.position.y,
+            s.position.z,
+            s.depth,
+        ] {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        for x in [s.object_id, s.material_id, self.count] {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut f = [0.0; 10];
+        for x in &mut f {
+            let mut buf = [0u8; 8];
+            r.read_exact(&mut buf)?;
+            *x = f64::from_le_bytes(buf);
+        }
+        let mut u = [0u32; 3];
+        for x in &mut u {
+            let mut buf = [0u8; 4];
+            r.read_exact(&mut buf)?;
+            *x = u32::from_le_bytes(buf);
+        }
+        Ok(Self {
+            sum: Aovs {
+                albedo: Color::new(f[0], f[1], f[2]),
+                normal: Vec3::new(f[3], f[4], f[5]),
+                position: Point3::new(f[6], f[7], f[8]),
+                depth: f[9],
+                object_id: u[0],
+                material_id: u[1],
+            },
+            count: u[2],
+        })
+    }
+}
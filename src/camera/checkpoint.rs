@@ -0,0 +1,108 @@
+use crate::camera::framebuffer::Framebuffer;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MAGIC: &[u8; 8] = b"RTCKPT03";
+
+// Periodically saved state of a progressive render: the accumulated framebuffer and the number
+// of finished passes. The fingerprint ties it to one scene and camera setup, the sample count is
+// left out so a finished render can be resumed with a higher one.
+pub(crate) struct Checkpoint {
+    pub path: PathBuf,
+    pub interval: Duration,
+    pub scene: String, // identifies the scene in the fingerprint, e.g. its name or file content
+    // The seed asked for, if any. Without one every run draws its own, which mixes fine.
+    pub seed: Option<u64>,
+}
+
+// FNV-1a, used instead of the std hasher because its output must not change between builds.
+pub(crate) fn fingerprint(description: &str) -> u64 {
+    description
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
+// Size and modification time of a file the scene reads, for the scene part of the fingerprint.
+pub(crate) fn file_stamp(path: &Path) -> String {
+    let modified = std::fs::metadata(path).and_then(|m| Ok((m.len(), m.modified()?)));
+    match modified {
+        Ok((len, time)) => {
+            let nanos = time
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos());
+            format!("{}:{}:{}", path.display(), len, nanos)
+        }
+        Err(_) => format!("{}:missing", path.display()),
+    }
+}
+
+// `path` with ".tmp" appended, so files of different extensions never share one.
+pub(crate) fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+impl Checkpoint {
+    // Returns the saved framebuffer and pass count, or None if there is no checkpoint yet.
+    pub(crate) fn load(
+        &self,
+        fingerprint: u64,
+        width: u32,
+        height: u32,
+    ) -> io::Result<Option<(Framebuffer, u32)>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut r = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("\"{}\" is not a checkpoint file", self.path.display()),
+            ));
+        }
+        let mut buf = [0u8; 8];
+        r.read_exact(&mut buf)?;
+        if u64::from_le_bytes(buf) != fingerprint {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint \"{}\" belongs to a different scene or camera setup",
+                    self.path.display()
+                ),
+            ));
+        }
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf)?;
+        let passes = u32::from_le_bytes(buf);
+
+        Ok(Some((Framebuffer::read(&mut r, width, height)?, passes)))
+    }
+
+    pub(crate) fn save(&self, fingerprint: u64, fb: &Framebuffer, passes: u32) -> io::Result<()> {
+        if let Some(prefix) = self.path.parent() {
+            std::fs::create_dir_all(prefix)?;
+        }
+
+        // Write next to the old checkpoint first, so a crash while saving never loses it.
+        let tmp = tmp_path(&self.path);
+        {
+            let mut w = BufWriter::new(File::create(&tmp)?);
+            w.write_all(MAGIC)?;
+            w.write_all(&fingerprint.to_le_bytes())?;
+            w.write_all(&passes.to_le_bytes())?;
+            fb.write(&mut w)?;
+            w.flush()?;
+        }
+        std::fs::rename(tmp, &self.path)
+    }
+}
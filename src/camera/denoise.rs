@@ -0,0 +1,184 @@
+use crate::camera::aov::Aovs;
+use crate::camera::framebuffer::Framebuffer;
+use crate::rtweekend::color::{Color, luminance};
+use crate::rtweekend::vec3::{Vec3, dot};
+use rayon::prelude::*;
+
+// Edge-avoiding à-trous wavelet filter after SVGF (Schied et al. 2017). The radiance is divided
+// by the first-hit albedo so textures survive the blur, then filtered with a 5x5 B3-spline
+// kernel whose taps spread further apart each iteration. The taps are weighted down across
+// normal and depth edges and where the luminance differs by more than the noise explains.
+const ITERATIONS: u32 = 5;
+const KERNEL: [f64; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const SIGMA_LUMINANCE: f64 = 4.0;
+const SIGMA_NORMAL: f64 = 128.0;
+const SIGMA_DEPTH: f64 = 1.0;
+
+struct Guide {
+    hit: bool,
+    normal: Vec3,
+    depth: f64,
+    // Change of depth per pixel, so depth edges are told apart from slanted surfaces.
+    depth_gradient: f64,
+}
+
+// Needs the AOVs of the framebuffer, returns the denoised radiance of every pixel.
+pub(crate) fn denoise(fb: &Framebuffer) -> Vec<Color> {
+    let (width, height) = (fb.width() as usize, fb.height() as usize);
+    let aovs: Vec<Aovs> = (0..fb.height())
+        .flat_map(|y| (0..fb.width()).map(move |x| fb.aovs(x, y)))
+        .collect();
+    let hit = |x: usize, y: usize| aovs[y * width + x].depth > 0.0;
+
+    let guides: Vec<Guide> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let a = &aovs[y * width + x];
+            let slope = |x0: usize, y0: usize, x1: usize, y1: usize| {
+                let span = (x1 - x0) + (y1 - y0);
+                if span > 0 && hit(x0, y0) && hit(x1, y1) {
+                    (aovs[y1 * width + x1].depth - aovs[y0 * width + x0].depth).abs() / span as f64
+                } else {
+                    0.0
+                }
+            };
+            let dx = slope(x.saturating_sub(1), y, (x + 1).min(width - 1), y);
+            let dy = slope(x, y.saturating_sub(1), x, (y + 1).min(height - 1));
+            Guide {
+                hit: hit(x, y),
+                normal: a.normal,
+                depth: a.depth,
+                depth_gradient: dx.max(dy),
+            }
+        })
+        .collect();
+
+    // Rays that hit nothing keep their radiance as it is.
+    let albedo: Vec<Color> = aovs
+        .iter()
+        .map(|a| {
+            if a.depth > 0.0 {
+                Color::new(
+                    a.albedo.x.max(0.01),
+                    a.albedo.y.max(0.01),
+                    a.albedo.z.max(0.01),
+                )
+            } else {
+                Color::new(1.0, 1.0, 1.0)
+            }
+        })
+        .collect();
+
+    let mut illumination = Vec::with_capacity(width * height);
+    let mut variance = Vec::with_capacity(width * height);
+    for y in 0..fb.height() {
+        for x in 0..fb.width() {
+            let index = y as usize * width + x as usize;
+            let c = fb.pixel(x, y);
+            let c = Color::new(
+                finite(c.x / albedo[index].x),
+                finite(c.y / albedo[index].y),
+                finite(c.z / albedo[index].z),
+            );
+            // Pixels without a variance estimate are assumed to be all noise.
+            let v = fb.samples(x, y).variance() / luminance(&albedo[index]).powi(2);
+            variance.push(if v.is_finite() {
+                v
+            } else {
+                luminance(&c).powi(2)
+            });
+            illumination.push(c);
+        }
+    }
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        let blurred = blur_variance(&variance, width, height);
+        let (next_illumination, next_variance): (Vec<Color>, Vec<f64>) = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                let p = &guides[index];
+                let lum_p = luminance(&illumination[index]);
+                let sigma_l = SIGMA_LUMINANCE * blurred[index].sqrt() + 1e-10;
+
+                let mut sum = Color::default();
+                let mut sum_variance = 0.0;
+                let mut sum_weight = 0.0;
+                for dy in -2i64..=2 {
+                    for dx in -2i64..=2 {
+                        let qx = x as i64 + dx * step;
+                        let qy = y as i64 + dy * step;
+                        if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                            continue;
+                        }
+                        let q_index = qy as usize * width + qx as usize;
+                        let q = &guides[q_index];
+                        if p.hit != q.hit {
+                            continue;
+                        }
+
+                        let mut weight =
+                            KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize];
+                        let lum_q = luminance(&illumination[q_index]);
+                        weight *= (-(lum_p - lum_q).abs() / sigma_l).exp();
+                        if p.hit {
+                            weight *= dot(&p.normal, &q.normal).max(0.0).powf(SIGMA_NORMAL);
+                            let distance = ((dx * dx + dy * dy) as f64).sqrt() * step as f64;
+                            let expected = SIGMA_DEPTH * p.depth_gradient * distance + 1e-10;
+                            weight *= (-(p.depth - q.depth).abs() / expected).exp();
+                        }
+
+                        sum += weight * illumination[q_index];
+                        sum_variance += weight * weight * variance[q_index];
+                        sum_weight += weight;
+                    }
+                }
+                // The center tap always has weight, unless the normal is degenerate.
+                if sum_weight > 0.0 {
+                    (sum / sum_weight, sum_variance / (sum_weight * sum_weight))
+                } else {
+                    (illumination[index], variance[index])
+                }
+            })
+            .unzip();
+        illumination = next_illumination;
+        variance = next_variance;
+    }
+
+    illumination
+        .iter()
+        .zip(&albedo)
+        .map(|(c, a)| *c * *a)
+        .collect()
+}
+
+fn finite(x: f64) -> f64 {
+    if x.is_finite() { x } else { 0.0 }
+}
+
+// 3x3 Gaussian blur of the variance, a single pixel's estimate is too noisy to steer the filter.
+fn blur_variance(variance: &[f64], width: usize, height: usize) -> Vec<f64> {
+    const GAUSSIAN: [f64; 2] = [1.0 / 2.0, 1.0 / 4.0];
+    (0..width * height)
+        .into_par_iter()
+        .map(|index| {
+            let (x, y) = ((index % width) as i64, (index / width) as i64);
+            let mut sum = 0.0;
+            let mut sum_weight = 0.0;
+            for dy in -1i64..=1 {
+                for dx in -1i64..=1 {
+                    let (qx, qy) = (x + dx, y + dy);
+                    if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                        continue;
+                    }
+                    let weight =
+                        GAUSSIAN[dx.unsigned_abs() as usize] * GAUSSIAN[dy.unsigned_abs() as usize];
+                    sum += weight * variance[qy as usize * width + qx as usize];
+                    sum_weight += weight;
+                }
+            }
+            sum / sum_weight
+        })
+        .collect()
+}
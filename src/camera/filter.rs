@@ -0,0 +1,160 @@
+use crate::rtweekend::PI;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Pixel reconstruction filter. Filters are separable, a sample at offset (dx, dy) from a pixel
+// center is weighted with evaluate(dx) * evaluate(dy), and ignored beyond the radius.
+pub(crate) trait Filter: Send + Sync {
+    fn radius(&self) -> f64;
+
+    fn evaluate(&self, x: f64) -> f64;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum FilterKind {
+    #[default]
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "blackman-harris" => Ok(FilterKind::BlackmanHarris),
+            _ => Err(format!(
+                "unknown filter \"{}\", expected box, tent, gaussian, mitchell or blackman-harris",
+                s
+            )),
+        }
+    }
+}
+
+impl FilterKind {
+    pub(crate) fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::BlackmanHarris => 2.0,
+        }
+    }
+
+    pub(crate) fn build(self, radius: f64) -> Arc<dyn Filter> {
+        match self {
+            FilterKind::Box => Arc::new(BoxFilter { radius }),
+            FilterKind::Tent => Arc::new(TentFilter { radius }),
+            FilterKind::Gaussian => Arc::new(GaussianFilter { radius }),
+            FilterKind::Mitchell => Arc::new(MitchellFilter { radius }),
+            FilterKind::BlackmanHarris => Arc::new(BlackmanHarrisFilter { radius }),
+        }
+    }
+}
+
+// With the default radius of half a pixel every sample only counts for its own pixel.
+struct BoxFilter {
+    radius: f64,
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        if x.abs() <= self.radius { 1.0 } else { 0.0 }
+    }
+}
+
+struct TentFilter {
+    radius: f64,
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        (1.0 - x.abs() / self.radius).max(0.0)
+    }
+}
+
+// Gaussian with a standard deviation of a third of the radius, shifted down so it reaches zero
+// at the radius instead of being cut off.
+struct GaussianFilter {
+    radius: f64,
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        let sigma = self.radius / 3.0;
+        let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+        (gaussian(x) - gaussian(self.radius)).max(0.0)
+    }
+}
+
+// Mitchell-Netravali with B = C = 1/3. Its negative lobes sharpen edges, so pixel weights can
+// drop below zero.
+struct MitchellFilter {
+    radius: f64,
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        const B: f64 = 1.0 / 3.0;
+        const C: f64 = 1.0 / 3.0;
+        // The cubic is defined on [-2, 2].
+        let x = (2.0 * x / self.radius).abs();
+        let value = if x < 1.0 {
+            (12.0 - 9.0 * B - 6.0 * C) * x * x * x
+                + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+                + (6.0 - 2.0 * B)
+        } else if x < 2.0 {
+            (-B - 6.0 * C) * x * x * x
+                + (6.0 * B + 30.0 * C) * x * x
+                + (-12.0 * B - 48.0 * C) * x
+                + (8.0 * B + 24.0 * C)
+        } else {
+            0.0
+        };
+        value / 6.0
+    }
+}
+
+// The four term Blackman-Harris window stretched over the filter width.
+struct BlackmanHarrisFilter {
+    radius: f64,
+}
+
+impl Filter for BlackmanHarrisFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        if x.abs() >= self.radius {
+            return 0.0;
+        }
+        let t = (x / self.radius + 1.0) / 2.0;
+        0.35875 - 0.48829 * (2.0 * PI * t).cos() + 0.14128 * (4.0 * PI * t).cos()
+            - 0.01168 * (6.0 * PI * t).cos()
+    }
+}
@@ -0,0 +1,387 @@
+use crate::camera::aov::{AovSamples, Aovs};
+use crate::camera::filter::Filter;
+use crate::rtweekend::color::{self, Color, ToneMapping, luminance};
+use image::{ImageBuffer, RgbImage};
+use std::io::{self, Read, Write};
+
+// Samples taken for one pixel. Besides the radiance sum, the luminance mean and the sum of
+// squared deviations (m2) are tracked with Welford's algorithm to estimate the pixel variance.
+// The coverage counts the samples whose camera ray hit geometry and becomes the alpha channel.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct PixelSamples {
+    sum: Color,
+    coverage: f64,
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl PixelSamples {
+    pub(crate) fn add(&mut self, color: Color, hit: bool) {
+        self.sum += color;
+        if hit {
+            self.coverage += 1.0;
+        }
+        self.count += 1;
+
+        let lum = luminance(&color);
+        let lum = if lum.is_finite() { lum } else { 0.0 };
+        let delta = lum - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (lum - self.mean);
+    }
+
+    // Combines two independent sets of samples (Chan et al.'s parallel variance update).
+    pub(crate) fn merge(&mut self, other: &PixelSamples) {
+        if other.count == 0 {
+            return;
+        }
+        let n_a = self.count as f64;
+        let n_b = other.count as f64;
+        let n = n_a + n_b;
+        let delta = other.mean - self.mean;
+
+        self.sum += other.sum;
+        self.coverage += other.coverage;
+        self.count += other.count;
+        self.mean += delta * n_b / n;
+        self.m2 += other.m2 + delta * delta * n_a * n_b / n;
+    }
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        for x in [
+            self.sum.x,
+            self.sum.y,
+            self.sum.z,
+            self.coverage,
+            self.mean,
+            self.m2,
+        ] {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        w.write_all(&self.count.to_le_bytes())
+    }
+
+    fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut f = [0.0; 6];
+        for x in &mut f {
+            let mut buf = [0u8; 8];
+            r.read_exact(&mut buf)?;
+            *x = f64::from_le_bytes(buf);
+        }
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf)?;
+        Ok(Self {
+            sum: Color::new(f[0], f[1], f[2]),
+            coverage: f[3],
+            count: u32::from_le_bytes(buf),
+            mean: f[4],
+            m2: f[5],
+        })
+    }
+
+    pub(crate) fn count(&self) -> u32 {
+        self.count
+    }
+
+    // Variance of the mean luminance, unknown (infinite) before the second sample.
+    pub(crate) fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        self.m2 / (self.count - 1) as f64 / self.count as f64
+    }
+
+    // Standard error of the mean luminance relative to the mean itself.
+    pub(crate) fn relative_error(&self) -> f64 {
+        self.variance().sqrt() / self.mean.max(1e-3)
+    }
+}
+
+// Filter weighted sums of the samples that landed near a pixel.
+#[derive(Clone, Copy, Default)]
+struct Splat {
+    color: Color,
+    coverage: f64,
+    weight: f64,
+}
+
+impl Splat {
+    fn add(&mut self, other: &Splat) {
+        self.color += other.color;
+        self.coverage += other.coverage;
+        self.weight += other.weight;
+    }
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        for x in [
+            self.color.x,
+            self.color.y,
+            self.color.z,
+            self.coverage,
+            self.weight,
+        ] {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut f = [0.0; 5];
+        for x in &mut f {
+            let mut buf = [0u8; 8];
+            r.read_exact(&mut buf)?;
+            *x = f64::from_le_bytes(buf);
+        }
+        Ok(Self {
+            color: Color::new(f[0], f[1], f[2]),
+            coverage: f[3],
+            weight: f[4],
+        })
+    }
+}
+
+// Splats of the samples of one tile. The buffer reaches past the tile by the filter radius, so
+// threads splat into their own copy and merge it into the framebuffer when the tile is done.
+pub(crate) struct SplatBuffer {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    splats: Vec<Splat>,
+}
+
+impl SplatBuffer {
+    // Covers the pixels from (x0, y0) to (x1, y1) inclusive plus the filter radius, clipped to
+    // the image.
+    pub(crate) fn new(
+        fb: &Framebuffer,
+        (x0, y0): (u32, u32),
+        (x1, y1): (u32, u32),
+        radius: f64,
+    ) -> Self {
+        let border = radius.ceil() as u32;
+        let left = x0.saturating_sub(border);
+        let top = y0.saturating_sub(border);
+        let right = (x1 + border).min(fb.width - 1);
+        let bottom = (y1 + border).min(fb.height - 1);
+        let width = right - left + 1;
+        let height = bottom - top + 1;
+        Self {
+            x0: left,
+            y0: top,
+            width,
+            height,
+            splats: vec![Splat::default(); (width * height) as usize],
+        }
+    }
+
+    // Adds a sample taken at the continuous image position (x, y) to every pixel whose center
+    // is within the filter radius.
+    pub(crate) fn splat(&mut self, filter: &dyn Filter, x: f64, y: f64, color: Color, hit: bool) {
+        let radius = filter.radius();
+        let left = ((x - radius - 0.5).ceil() as i64).max(self.x0 as i64);
+        let right = ((x + radius - 0.5).floor() as i64).min((self.x0 + self.width - 1) as i64);
+        let top = ((y - radius - 0.5).ceil() as i64).max(self.y0 as i64);
+        let bottom = ((y + radius - 0.5).floor() as i64).min((self.y0 + self.height - 1) as i64);
+
+        for py in top..=bottom {
+            let wy = filter.evaluate(py as f64 + 0.5 - y);
+            if wy == 0.0 {
+                continue;
+            }
+            for px in left..=right {
+                let weight = wy * filter.evaluate(px as f64 + 0.5 - x);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = (py as u32 - self.y0) * self.width + (px as u32 - self.x0);
+                let splat = &mut self.splats[index as usize];
+                splat.color += weight * color;
+                splat.coverage += if hit { weight } else { 0.0 };
+                splat.weight += weight;
+            }
+        }
+    }
+}
+
+// Linear radiance accumulated by the renderer, so the buffer can be averaged, tone mapped or
+// refined with more samples later. The pixel values come from the filtered splats, the samples
+// of each pixel itself are tracked separately for the adaptive sampling statistics.
+#[derive(Clone)]
+pub(crate) struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<PixelSamples>,
+    splats: Vec<Splat>,
+    aovs: Vec<AovSamples>,
+    // Replaces the filtered radiance once the render went through the denoiser.
+    denoised: Option<Vec<Color>>,
+}
+
+impl Framebuffer {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![PixelSamples::default(); (width * height) as usize],
+            splats: vec![Splat::default(); (width * height) as usize],
+            aovs: Vec::new(),
+            denoised: None,
+        }
+    }
+
+    pub(crate) fn set_aovs(&mut self, enabled: bool) {
+        if !enabled {
+            self.aovs = Vec::new();
+        } else if self.aovs.is_empty() {
+            self.aovs = vec![AovSamples::default(); (self.width * self.height) as usize];
+        }
+    }
+
+    pub(crate) fn has_aovs(&self) -> bool {
+        !self.aovs.is_empty()
+    }
+
+    pub(crate) fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub(crate) fn add_samples(&mut self, x: u32, y: u32, samples: &PixelSamples) {
+        let index = self.index(x, y);
+        self.pixels[index].merge(samples);
+    }
+
+    pub(crate) fn add_aovs(&mut self, x: u32, y: u32, aovs: &AovSamples) {
+        let index = self.index(x, y);
+        if let Some(pixel) = self.aovs.get_mut(index) {
+            pixel.merge(aovs);
+        }
+    }
+
+    pub(crate) fn add_splats(&mut self, buffer: &SplatBuffer) {
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                let index = self.index(buffer.x0 + x, buffer.y0 + y);
+                self.splats[index].add(&buffer.splats[(y * buffer.width + x) as usize]);
+            }
+        }
+    }
+
+    pub(crate) fn samples(&self, x: u32, y: u32) -> &PixelSamples {
+        &self.pixels[self.index(x, y)]
+    }
+
+    pub(crate) fn total_samples(&self) -> u64 {
+        self.pixels.iter().map(|p| p.count as u64).sum()
+    }
+
+    // Filtered radiance of a pixel, black if no sample reached it yet. Filters with negative
+    // lobes can leave a pixel without usable weight, it falls back to the mean of its own
+    // samples then.
+    pub(crate) fn pixel(&self, x: u32, y: u32) -> Color {
+        let index = self.index(x, y);
+        if let Some(denoised) = &self.denoised {
+            return denoised[index];
+        }
+        let splat = &self.splats[index];
+        let p = &self.pixels[index];
+        if splat.weight > 1e-6 {
+            splat.color / splat.weight
+        } else if p.count > 0 {
+            p.sum / p.count as f64
+        } else {
+            Color::default()
+        }
+    }
+
+    pub(crate) fn alpha(&self, x: u32, y: u32) -> f64 {
+        let index = self.index(x, y);
+        let splat = &self.splats[index];
+        let p = &self.pixels[index];
+        if splat.weight > 1e-6 {
+            (splat.coverage / splat.weight).clamp(0.0, 1.0)
+        } else if p.count > 0 {
+            p.coverage / p.count as f64
+        } else {
+            0.0
+        }
+    }
+
+    pub(crate) fn set_denoised(&mut self, pixels: Vec<Color>) {
+        self.denoised = Some(pixels);
+    }
+
+    // Averaged AOVs of a pixel, all zero if no sample hit anything or AOVs are disabled.
+    pub(crate) fn aovs(&self, x: u32, y: u32) -> Aovs {
+        self.aovs
+            .get(self.index(x, y))
+            .map(|a| a.mean())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&self.width.to_le_bytes())?;
+        w.write_all(&self.height.to_le_bytes())?;
+        for p in &self.pixels {
+            p.write(w)?;
+        }
+        for splat in &self.splats {
+            splat.write(w)?;
+        }
+        w.write_all(&[self.has_aovs() as u8])?;
+        for a in &self.aovs {
+            a.write(w)?;
+        }
+        Ok(())
+    }
+
+    // Fails unless the saved size is `width` by `height`, checked before anything is allocated.
+    pub(crate) fn read(r: &mut impl Read, width: u32, height: u32) -> io::Result<Self> {
+        let mut buf = [0u8; 4];
+        r.read_exact(&mut buf)?;
+        let saved_width = u32::from_le_bytes(buf);
+        r.read_exact(&mut buf)?;
+        let saved_height = u32::from_le_bytes(buf);
+        if (saved_width, saved_height) != (width, height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "saved image is {}x{}, expected {}x{}",
+                    saved_width, saved_height, width, height
+                ),
+            ));
+        }
+
+        let mut fb = Self::new(width, height);
+        for p in &mut fb.pixels {
+            *p = PixelSamples::read(r)?;
+        }
+        for splat in &mut fb.splats {
+            *splat = Splat::read(r)?;
+        }
+        let mut flag = [0u8; 1];
+        r.read_exact(&mut flag)?;
+        fb.set_aovs(flag[0] != 0);
+        for a in &mut fb.aovs {
+            *a = AovSamples::read(r)?;
+        }
+        Ok(fb)
+    }
+
+    pub(crate) fn to_rgb8(&self, tone_mapping: &ToneMapping) -> RgbImage {
+        let mut img: RgbImage = ImageBuffer::new(self.width, self.height);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            color::write_color(pixel, &self.pixel(x, y), tone_mapping);
+        }
+        img
+    }
+}
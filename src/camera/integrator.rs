@@ -0,0 +1,70 @@
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::rtweekend::color::Color;
+use crate::rtweekend::vec3::ray::Ray;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub(crate) mod debug;
+pub(crate) mod path;
+
+// Computes what the camera sees along a ray, the radiance for the path tracer or a false color
+// for the debug views.
+pub(crate) trait Integrator: Send + Sync {
+    // Also returns the first hit of the ray, None if it hit no geometry at all. Whether there is
+    // one becomes the alpha channel, and the AOVs are filled from it.
+    fn ray_color(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        lights: &Arc<dyn Hittable>,
+    ) -> (Color, Option<HitRecord>);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum IntegratorKind {
+    // Path tracing, sampling one direction per bounce from a 50/50 mixture of the light and
+    // material pdfs.
+    #[default]
+    Mixture,
+    // Path tracing with next event estimation plus material sampling, combined with multiple
+    // importance sampling.
+    Mis,
+    // Shading normal, mapped from [-1,1] to [0,1] per axis.
+    Normals,
+    // Texture coordinates in the red and green channels.
+    Uv,
+    // Distance to the first hit, one at twice the distance from lookfrom to lookat.
+    Depth,
+    // Fraction of cosine weighted rays that escape within the ambient occlusion radius.
+    AmbientOcclusion,
+    // A color per material, picked by hashing it.
+    MaterialId,
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mixture" => Ok(IntegratorKind::Mixture),
+            "mis" => Ok(IntegratorKind::Mis),
+            "normals" => Ok(IntegratorKind::Normals),
+            "uv" => Ok(IntegratorKind::Uv),
+            "depth" => Ok(IntegratorKind::Depth),
+            "ao" => Ok(IntegratorKind::AmbientOcclusion),
+            "material-id" => Ok(IntegratorKind::MaterialId),
+            _ => Err(format!(
+                "unknown integrator \"{}\", expected mixture, mis, normals, uv, depth, ao or material-id",
+                s
+            )),
+        }
+    }
+}
+
+// Veach's power heuristic with an exponent of 2, the weight of a sample taken with pdf `a` when
+// the other strategy would have taken it with pdf `b`.
+pub(crate) fn power_heuristic(a: f64, b: f64) -> f64 {
+    let a2 = a * a;
+    let b2 = b * b;
+    if a2 + b2 > 0.0 { a2 / (a2 + b2) } else { 0.0 }
+}
@@ -0,0 +1,131 @@
+use crate::camera::aov::MaterialIds;
+use crate::camera::integrator::Integrator;
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::material::onb::Onb;
+use crate::rtweekend::color::Color;
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::sampler::{self, Dimension};
+use crate::rtweekend::vec3::ray::Ray;
+use crate::rtweekend::vec3::{dot, sample_cosine_direction};
+use crate::rtweekend::{self, vec3};
+use std::sync::Arc;
+
+// Views of the first hit that help to find broken geometry, e.g. flipped normals or missing
+// texture coordinates of an imported model. Rays that miss everything stay black.
+pub(crate) fn first_hit(r: &Ray, world: &dyn Hittable) -> Option<HitRecord> {
+    let mut rec = HitRecord::new();
+    world
+        .hit(r, &mut Interval::new(0.001, f64::INFINITY), &mut rec)
+        .then_some(rec)
+}
+
+pub(crate) struct NormalsView;
+
+impl Integrator for NormalsView {
+    fn ray_color(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        _lights: &Arc<dyn Hittable>,
+    ) -> (Color, Option<HitRecord>) {
+        match first_hit(r, world) {
+            Some(rec) => (0.5 * (rec.normal + Color::new(1.0, 1.0, 1.0)), Some(rec)),
+            None => (Color::default(), None),
+        }
+    }
+}
+
+pub(crate) struct UvView;
+
+impl Integrator for UvView {
+    fn ray_color(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        _lights: &Arc<dyn Hittable>,
+    ) -> (Color, Option<HitRecord>) {
+        match first_hit(r, world) {
+            Some(rec) => (Color::new(rec.u, rec.v, 0.0), Some(rec)),
+            None => (Color::default(), None),
+        }
+    }
+}
+
+pub(crate) struct DepthView {
+    pub scale: f64, // applied to the distance along the ray
+}
+
+impl Integrator for DepthView {
+    fn ray_color(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        _lights: &Arc<dyn Hittable>,
+    ) -> (Color, Option<HitRecord>) {
+        match first_hit(r, world) {
+            Some(rec) => {
+                let distance = (rec.p - r.origin).length() * self.scale;
+                (Color::new(distance, distance, distance), Some(rec))
+            }
+            None => (Color::default(), None),
+        }
+    }
+}
+
+// One cosine weighted ray per sample from the first hit, white if it escapes within the
+// radius.
+pub(crate) struct AmbientOcclusion {
+    pub radius: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn ray_color(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        _lights: &Arc<dyn Hittable>,
+    ) -> (Color, Option<HitRecord>) {
+        let Some(rec) = first_hit(r, world) else {
+            return (Color::default(), None);
+        };
+
+        // Open the hemisphere on the side the camera ray came from.
+        let normal = if dot(&rec.normal, &r.direction) > 0.0 {
+            -rec.normal
+        } else {
+            rec.normal
+        };
+        let (r1, r2) = sampler::sample_2d(Dimension::Bsdf);
+        let direction = Onb::new(&normal).transform(&sample_cosine_direction(r1, r2));
+        let occlusion_ray = Ray::new_move(rec.p, vec3::unit_vector(&direction), r.time);
+        let mut occluder = HitRecord::new();
+        let occluded = world.hit(
+            &occlusion_ray,
+            &mut Interval::new(0.001, self.radius),
+            &mut occluder,
+        );
+        let visibility = if occluded { 0.0 } else { 1.0 };
+        (Color::new(visibility, visibility, visibility), Some(rec))
+    }
+}
+
+pub(crate) struct MaterialIdView {
+    pub material_ids: Arc<MaterialIds>,
+}
+
+impl Integrator for MaterialIdView {
+    fn ray_color(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        _lights: &Arc<dyn Hittable>,
+    ) -> (Color, Option<HitRecord>) {
+        let Some(rec) = first_hit(r, world) else {
+            return (Color::default(), None);
+        };
+
+        let h = rtweekend::mix(self.material_ids.get(&rec.mat) as u64);
+        let channel = |shift: u32| 0.15 + 0.85 * ((h >> shift) & 0xff) as f64 / 255.0;
+        (Color::new(channel(0), channel(8), channel(16)), Some(rec))
+    }
+}
@@ -0,0 +1,156 @@
+use crate::camera::integrator::{Integrator, power_heuristic};
+use crate::material::hittable::{self, Hittable};
+use crate::material::{ScatterKind, ScatterRecord};
+use crate::pdf::{HittablePdf, MixturePdf, Pdf};
+use crate::rtweekend::color::Color;
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::sampler::{self, Dimension};
+use crate::rtweekend::vec3::ray::Ray;
+use std::sync::Arc;
+
+// The path tracer. With `mis` off every bounce samples one direction from a 50/50 mixture of
+// the light and material pdfs, with it on the lights are sampled explicitly and combined with
+// the material sample by multiple importance sampling.
+pub(crate) struct PathTracer {
+    pub mis: bool,
+    pub background: Color,
+    pub max_depth: i32,
+    pub max_diffuse_depth: Option<i32>,
+    pub max_specular_depth: Option<i32>,
+    pub max_transmission_depth: Option<i32>,
+    pub russian_roulette_depth: Option<i32>,
+}
+
+impl Integrator for PathTracer {
+    // The path is followed in a loop, `throughput` is the share of the light leaving the current
+    // vertex that reaches the camera.
+    fn ray_color(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        lights: &Arc<dyn Hittable>,
+    ) -> (Color, Option<hittable::HitRecord>) {
+        let mut color = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = *r;
+        let mut first_hit = None;
+        // Pdf with which the material at the previous vertex chose `ray`, for the MIS weight of
+        // emission found by it. None for camera rays and specular bounces.
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut diffuse_depth = 0;
+        let mut specular_depth = 0;
+        let mut transmission_depth = 0;
+
+        for bounce in 0..self.max_depth {
+            sampler::start_bounce(bounce as u32);
+            let mut rec: hittable::HitRecord = hittable::HitRecord::new();
+            if !world.hit(&ray, &mut Interval::new(0.001, f64::INFINITY), &mut rec) {
+                color += throughput * self.background;
+                break;
+            }
+            if bounce == 0 {
+                first_hit = Some(rec.clone());
+            }
+
+            let mut color_from_emission = rec.mat.emitted(&ray, &rec, rec.u, rec.v, &rec.p);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                if color_from_emission.length_squared() > 0.0 {
+                    let light_pdf = lights.pdf_value(&ray.origin, &ray.direction);
+                    color_from_emission *= power_heuristic(bsdf_pdf, light_pdf);
+                }
+            }
+            color += throughput * color_from_emission;
+
+            let mut srec = ScatterRecord::default();
+            if !rec.mat.scatter(&ray, &rec, &mut srec) {
+                break;
+            }
+
+            // Stop before a bounce that would break one of the depth limits.
+            let (depth, max) = match srec.kind {
+                ScatterKind::Diffuse => (&mut diffuse_depth, self.max_diffuse_depth),
+                ScatterKind::Specular => (&mut specular_depth, self.max_specular_depth),
+                ScatterKind::Transmission => (&mut transmission_depth, self.max_transmission_depth),
+            };
+            if bounce + 1 >= self.max_depth || max.is_some_and(|max| *depth >= max) {
+                break;
+            }
+            *depth += 1;
+
+            if srec.skip_pdf {
+                throughput = throughput * srec.attenuation;
+                ray = srec.skip_pdf_ray;
+                bsdf_pdf = None;
+            } else {
+                let p: Arc<dyn Pdf> = if self.mis {
+                    color += throughput * sample_lights(&ray, &rec, &srec, world, lights.as_ref());
+                    srec.pdf_ptr.clone()
+                } else {
+                    let light_ptr = Arc::new(HittablePdf::new(lights.clone(), rec.p));
+                    Arc::new(MixturePdf::new(light_ptr, srec.pdf_ptr.clone()))
+                };
+
+                let scattered = Ray::new_move(rec.p, p.generate(), ray.time);
+                let pdf_value = p.value(&scattered.direction);
+                if pdf_value <= 0.0 {
+                    break;
+                }
+                let scattering_pdf = rec.mat.scattering_pdf(&ray, &rec, &scattered);
+                throughput = throughput * srec.attenuation * scattering_pdf / pdf_value;
+                ray = scattered;
+                bsdf_pdf = self.mis.then_some(pdf_value);
+            }
+
+            // Russian roulette: paths that carry little light are ended at random, the
+            // survivors are weighted up so the estimate stays unbiased.
+            if self
+                .russian_roulette_depth
+                .is_some_and(|depth| bounce + 1 >= depth)
+            {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if sampler::sample_1d(Dimension::Roulette) >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+
+        (color, first_hit)
+    }
+}
+
+// Next event estimation for MIS: light arriving from a direction sampled from the lights,
+// weighted against the chance that the material would have sampled it.
+fn sample_lights(
+    r: &Ray,
+    rec: &hittable::HitRecord,
+    srec: &ScatterRecord,
+    world: &dyn Hittable,
+    lights: &dyn Hittable,
+) -> Color {
+    let to_light = Ray::new_move(rec.p, sampler::next_event(|| lights.random(&rec.p)), r.time);
+    let light_pdf = lights.pdf_value(&rec.p, &to_light.direction);
+    let mut light_rec = hittable::HitRecord::new();
+    if light_pdf <= 0.0
+        || !sampler::next_event(|| {
+            world.hit(
+                &to_light,
+                &mut Interval::new(0.001, f64::INFINITY),
+                &mut light_rec,
+            )
+        })
+    {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let emitted = light_rec.mat.emitted(
+        &to_light,
+        &light_rec,
+        light_rec.u,
+        light_rec.v,
+        &light_rec.p,
+    );
+    let bsdf_pdf = srec.pdf_ptr.value(&to_light.direction).max(0.0);
+    let scattering_pdf = rec.mat.scattering_pdf(r, rec, &to_light);
+    srec.attenuation * scattering_pdf * emitted * power_heuristic(light_pdf, bsdf_pdf) / light_pdf
+}
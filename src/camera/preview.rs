@@ -0,0 +1,32 @@
+use crate::camera::checkpoint;
+use crate::camera::framebuffer::Framebuffer;
+use crate::rtweekend::color::ToneMapping;
+use image::{ImageFormat, RgbImage};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Image of the render in progress, rewritten while tiles finish. Tiles that have not been
+// sampled yet stay black.
+pub(crate) struct Preview {
+    pub path: PathBuf,
+    pub interval: Duration,
+    pub tone_mapping: ToneMapping,
+}
+
+impl Preview {
+    pub(crate) fn image(&self, fb: &Framebuffer) -> RgbImage {
+        fb.to_rgb8(&self.tone_mapping)
+    }
+
+    // Written to a temporary file first, so viewers never pick up a half written image.
+    pub(crate) fn save(&self, img: &RgbImage) -> io::Result<()> {
+        if let Some(prefix) = self.path.parent() {
+            std::fs::create_dir_all(prefix)?;
+        }
+        let tmp = checkpoint::tmp_path(&self.path);
+        img.save_with_format(&tmp, ImageFormat::Png)
+            .map_err(io::Error::other)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
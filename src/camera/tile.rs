@@ -0,0 +1,34 @@
+// Edge length of the square blocks of pixels handed to the render threads.
+pub(crate) const TILE_SIZE: u32 = 32;
+
+// Splits the image into tiles, each a list of its pixels. The tiles are ordered in a spiral
+// from the center outwards, so the middle of the image, usually the subject, is finished first.
+pub(crate) fn spiral_tiles(width: u32, height: u32) -> Vec<Vec<(u32, u32)>> {
+    let tiles_x = width.div_ceil(TILE_SIZE);
+    let tiles_y = height.div_ceil(TILE_SIZE);
+    let center_x = (tiles_x - 1) as f64 / 2.0;
+    let center_y = (tiles_y - 1) as f64 / 2.0;
+
+    let mut order: Vec<(f64, f64, u32, u32)> = (0..tiles_y)
+        .flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty)))
+        .map(|(tx, ty)| {
+            let dx = tx as f64 - center_x;
+            let dy = ty as f64 - center_y;
+            // The ring is the Chebyshev distance to the center, within a ring tiles go around
+            // by angle.
+            (dx.abs().max(dy.abs()), dy.atan2(dx), tx, ty)
+        })
+        .collect();
+    order.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+    order
+        .into_iter()
+        .map(|(_, _, tx, ty)| {
+            let x0 = tx * TILE_SIZE;
+            let y0 = ty * TILE_SIZE;
+            (y0..(y0 + TILE_SIZE).min(height))
+                .flat_map(|j| (x0..(x0 + TILE_SIZE).min(width)).map(move |i| (i, j)))
+                .collect()
+        })
+        .collect()
+}
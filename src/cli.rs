@@ -0,0 +1,172 @@
+use crate::camera::filter::FilterKind;
+use crate::camera::integrator::IntegratorKind;
+use crate::material::hittable::bvh::build::SplitMethod;
+use crate::rtweekend::color::ToneMap;
+use crate::rtweekend::sampler::SamplerKind;
+use clap::Parser;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(
+    name = "raytracer",
+    about = "Render a built-in scene or a scene description file"
+)]
+pub(crate) struct Cli {
+    /// Built-in scene name or path to a .json/.yaml scene file
+    #[arg(default_value = "try_use_model")]
+    pub scene: String,
+
+    /// Print the built-in scenes and the files in `scenes/`, then exit
+    #[arg(long)]
+    pub list_scenes: bool,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub image_width: Option<u32>,
+
+    #[arg(long, value_parser = positive_f64)]
+    pub aspect_ratio: Option<f64>,
+
+    #[arg(long, visible_alias = "spp", value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples_per_pixel: Option<u32>,
+
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub max_depth: Option<i32>,
+
+    /// Limit for diffuse bounces along a path
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub max_diffuse_depth: Option<i32>,
+
+    /// Limit for mirror and glossy reflections along a path
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub max_specular_depth: Option<i32>,
+
+    /// Limit for refractions along a path
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub max_transmission_depth: Option<i32>,
+
+    /// Bounces after which paths carrying little light may be ended by Russian roulette, off
+    /// unless given
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub rr_depth: Option<i32>,
+
+    /// Stop sampling a pixel once the relative error of its mean drops below this value
+    #[arg(long, value_parser = positive_f64)]
+    pub adaptive_threshold: Option<f64>,
+
+    /// Keep adding sample passes for this many seconds, ignoring the samples per pixel
+    #[arg(long, value_parser = positive_f64)]
+    pub time_budget: Option<f64>,
+
+    /// Save the progress to this file and resume from it if it exists
+    #[arg(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Seconds between two checkpoint saves
+    #[arg(long, default_value_t = 60.0, value_parser = positive_f64)]
+    pub checkpoint_interval: f64,
+
+    /// Periodically write the render in progress to this PNG file
+    #[arg(long)]
+    pub preview: Option<PathBuf>,
+
+    /// Seconds between two preview updates
+    #[arg(long, default_value_t = 2.0, value_parser = positive_f64)]
+    pub preview_interval: f64,
+
+    /// Vertical field of view in degrees
+    #[arg(long, value_parser = field_of_view)]
+    pub vfov: Option<f64>,
+
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub threads: Option<u64>,
+
+    /// Path tracer (mixture or mis) or debug view (normals, uv, depth, ao or material-id)
+    #[arg(long, default_value = "mixture")]
+    pub integrator: IntegratorKind,
+
+    /// Occlusion distance of the ao view in scene units
+    #[arg(long, default_value_t = 100.0, value_parser = positive_f64)]
+    pub ao_radius: f64,
+
+    /// Sample sequence: independent, stratified, halton, sobol or blue-noise
+    #[arg(long, default_value = "stratified")]
+    pub sampler: SamplerKind,
+
+    /// Pixel filter: box, tent, gaussian, mitchell or blackman-harris
+    #[arg(long, default_value = "box")]
+    pub filter: FilterKind,
+
+    /// Filter radius in pixels, defaults to a radius suited to the filter
+    #[arg(long, value_parser = positive_f64)]
+    pub filter_radius: Option<f64>,
+
+    /// How BVH nodes are split: median, binned-sah or sweep-sah (slowest to build, best trees)
+    #[arg(long, default_value = "binned-sah")]
+    pub bvh_split: SplitMethod,
+
+    /// Most primitives in a BVH leaf
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    pub bvh_leaf_size: u64,
+
+    /// Print the depth, node count and estimated SAH cost of every BVH built
+    #[arg(long)]
+    pub bvh_stats: bool,
+
+    /// Seed for the random numbers, a seeded render is identical with any number of threads
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    #[arg(short, long, default_value = "output/Try/image12.png")]
+    pub output: PathBuf,
+
+    /// Image format (png, jpg, exr, hdr, ...), replaces the extension of the output path
+    #[arg(long)]
+    pub format: Option<String>,
+
+    /// Write OpenEXR channels as 16-bit half floats
+    #[arg(long)]
+    pub half: bool,
+
+    /// Write the camera ray coverage as an alpha channel into OpenEXR files
+    #[arg(long)]
+    pub alpha: bool,
+
+    /// Remove the noise of low sample counts with a filter guided by the first-hit albedo,
+    /// normal and depth
+    #[arg(long)]
+    pub denoise: bool,
+
+    /// Also write first-hit albedo, normal, position, depth, object and material ids, as extra
+    /// OpenEXR layers or as `<name>.<aov>.exr` files next to other formats
+    #[arg(long)]
+    pub aovs: bool,
+
+    /// Tone mapping for 8-bit output: clamp, reinhard, reinhard-extended, aces or agx
+    #[arg(long, default_value = "clamp")]
+    pub tonemap: ToneMap,
+
+    /// Exposure compensation in stops applied before tone mapping
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub exposure: f64,
+
+    /// Radiance mapped to white by the reinhard-extended curve
+    #[arg(long, default_value_t = 4.0, value_parser = positive_f64)]
+    pub white_point: f64,
+}
+
+fn positive_f64(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        Ok(_) => Err("must be a positive number".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn field_of_view(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(x) if x > 0.0 && x < 180.0 => Ok(x),
+        Ok(_) => Err("must be between 0 and 180 degrees".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
@@ -1,33 +1,216 @@
 mod camera;
+mod cli;
 mod material;
+mod output;
 mod pdf;
 mod rtweekend;
+mod scene;
 
 use crate::camera::Camera;
+use crate::camera::checkpoint::{self, Checkpoint};
+use crate::camera::preview::Preview;
+use crate::cli::Cli;
+use crate::material::hittable::bvh::build::{self, BvhOptions};
+use crate::material::hittable::bvh::world::World;
 use crate::material::hittable::hittable_list::HittableList;
 use crate::material::hittable::quad::{Quad, make_box};
 use crate::material::hittable::sphere::Sphere;
 use crate::material::hittable::triangle::Triangle;
 use crate::material::hittable::{Hittable, RotateY, Translate};
-use crate::material::texture::model::load_model;
+use crate::material::texture::model::{self, load_model};
 use crate::material::texture::rtw_stb_image::RtwImage;
 use crate::material::{Dielectric, DiffuseLight, Lambertian, Mapping, Material, Metal};
-use crate::rtweekend::color::Color;
+use crate::output::OutputOptions;
+use crate::rtweekend::color::{Color, ToneMapping};
 use crate::rtweekend::vec3::Point3;
+use crate::scene::Scene;
+use clap::Parser;
+use console::style;
+use image::ImageFormat;
 use rtweekend::vec3::Vec3;
+use std::path::Path;
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tobj::Model;
 
-fn main() {
+type SceneFn = fn() -> Result<Scene, String>;
+
+const SCENES: [(&str, SceneFn); 2] = [
+    ("cornell_box", cornell_box),
+    ("try_use_model", try_use_model),
+];
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    if cli.list_scenes {
+        list_scenes();
+        return ExitCode::SUCCESS;
+    }
+
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{} {}", style("error:").red().bold(), err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn list_scenes() {
+    println!("Built-in scenes:");
+    for (name, _) in SCENES {
+        println!("  {}", name);
+    }
+
+    let mut files: Vec<_> = std::fs::read_dir("scenes")
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("json" | "yaml" | "yml")
+            )
+        })
+        .collect();
+    files.sort();
+    if !files.is_empty() {
+        println!("Scene files:");
+        for file in files {
+            println!("  {}", file.display());
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let mut output = cli.output;
+    if let Some(format) = &cli.format {
+        output.set_extension(format);
+    }
+    match ImageFormat::from_path(&output) {
+        Ok(format) if format.writing_enabled() => {}
+        _ => return Err(format!("cannot write images as \"{}\"", output.display())),
+    }
+
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .map_err(|err| err.to_string())?;
+    }
+    if let Some(seed) = cli.seed {
+        rtweekend::set_seed(seed);
+    }
+    build::set_options(BvhOptions {
+        split: cli.bvh_split,
+        leaf_size: cli.bvh_leaf_size as usize,
+        stats: cli.bvh_stats,
+    });
+
     let start = Instant::now();
 
-    try_use_model();
+    // Identifies the scene in checkpoints: built-in scenes by name, scene files by content, both
+    // with the model files they load.
+    let mut scene_key;
+    let mut scene = match SCENES.iter().find(|(name, _)| *name == cli.scene) {
+        Some((_, build)) => {
+            scene_key = cli.scene.clone();
+            build()?
+        }
+        None if Path::new(&cli.scene).is_file() => {
+            scene_key = std::fs::read_to_string(&cli.scene).unwrap_or_default();
+            scene::load_scene(Path::new(&cli.scene)).map_err(|err| err.to_string())?
+        }
+        None => {
+            return Err(format!(
+                "\"{}\" is neither a built-in scene nor a scene file (see --list-scenes)",
+                cli.scene
+            ));
+        }
+    };
+    for file in model::model_files() {
+        scene_key.push('|');
+        scene_key.push_str(&checkpoint::file_stamp(Path::new(&file)));
+    }
+
+    let cam = &mut scene.camera;
+    if let Some(x) = cli.image_width {
+        cam.image_width = x;
+    }
+    if let Some(x) = cli.aspect_ratio {
+        cam.aspect_ratio = x;
+    }
+    if let Some(x) = cli.samples_per_pixel {
+        cam.samples_per_pixel = x;
+    }
+    if let Some(x) = cli.max_depth {
+        cam.max_depth = x;
+    }
+    if let Some(x) = cli.vfov {
+        cam.vfov = x;
+    }
+    if let Some(x) = cli.adaptive_threshold {
+        cam.adaptive_threshold = x;
+    }
+    if let Some(x) = cli.time_budget {
+        cam.time_budget = Some(Duration::from_secs_f64(x));
+    }
+    cam.max_diffuse_depth = cli.max_diffuse_depth;
+    cam.max_specular_depth = cli.max_specular_depth;
+    cam.max_transmission_depth = cli.max_transmission_depth;
+    cam.russian_roulette_depth = cli.rr_depth;
+    cam.integrator = cli.integrator;
+    cam.ao_radius = cli.ao_radius;
+    cam.sampler = cli.sampler;
+    cam.filter = cli.filter;
+    cam.filter_radius = cli.filter_radius;
+    cam.aovs = cli.aovs;
+    cam.denoise = cli.denoise;
+    if let Some(path) = cli.checkpoint {
+        cam.checkpoint = Some(Checkpoint {
+            path,
+            interval: Duration::from_secs_f64(cli.checkpoint_interval),
+            scene: scene_key,
+            seed: cli.seed,
+        });
+    }
+    let options = OutputOptions {
+        half: cli.half,
+        alpha: cli.alpha,
+        tone_mapping: ToneMapping {
+            operator: cli.tonemap,
+            exposure: cli.exposure,
+            white_point: cli.white_point,
+        },
+    };
+    if let Some(path) = cli.preview {
+        cam.preview = Some(Preview {
+            path,
+            interval: Duration::from_secs_f64(cli.preview_interval),
+            tone_mapping: options.tone_mapping,
+        });
+    }
+
+    // Every scene is rendered through a BVH over its objects.
+    let world = World::new(scene.world);
+    let render_start = Instant::now();
+    let fb = cam
+        .render(&world, Arc::new(scene.lights))
+        .map_err(|err| format!("render failed: {}", err))?;
+    let render_duration = render_start.elapsed();
+    output::save_image(&fb, &output, options)
+        .map_err(|err| format!("cannot save \"{}\": {}", output.display(), err))?;
 
     let duration = start.elapsed();
+    println!("BVH 构建: {:.2}秒", build::build_time().as_secs_f64());
+    println!("渲染: {:.2}秒", render_duration.as_secs_f64());
     println!("耗时: {:.2}秒", duration.as_secs_f64());
+    Ok(())
 }
-fn cornell_box() {
+
+fn cornell_box() -> Result<Scene, String> {
     let mut world: HittableList = HittableList::new();
 
     let red = Arc::new(Lambertian::new(&Color::new(0.65, 0.05, 0.05)));
@@ -126,10 +309,14 @@ fn cornell_box() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world, Arc::new(lights));
+    Ok(Scene {
+        world,
+        lights,
+        camera: cam,
+    })
 }
 
-fn try_use_model() {
+fn try_use_model() -> Result<Scene, String> {
     let mut world: HittableList = HittableList::new();
 
     // let model = get_models("cornell_box.obj", 1.0);
@@ -179,7 +366,7 @@ fn try_use_model() {
         90.0,
         Vec3::new(280.0, 100.0 + 50.0, 400.0),
         300.0,
-    );
+    )?;
 
     load_model(
         "minimalist-weedy/weedy.obj",
@@ -188,7 +375,7 @@ fn try_use_model() {
         180.0,
         Vec3::new(580.0, 510.0, 610.0),
         3000.0,
-    );
+    )?;
 
     load_model(
         "arknights-warehouse/source/Arknights_Warehouse/Warehouse.obj",
@@ -197,7 +384,7 @@ fn try_use_model() {
         180.0,
         Vec3::new(300.0, 0.0, 400.0),
         40.0,
-    );
+    )?;
 
     let light = Arc::new(DiffuseLight::new_color(&Color::new(35.0, 35.0, 35.0)));
     world.add(Arc::new(Quad::new(
@@ -272,5 +459,9 @@ fn try_use_model() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world, Arc::new(lights));
+    Ok(Scene {
+        world,
+        lights,
+        camera: cam,
+    })
 }
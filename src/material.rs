@@ -4,22 +4,33 @@ use crate::material::texture::Texture;
 use crate::material::texture::rtw_stb_image::RtwImage;
 use crate::pdf::{CosinePdf, Pdf, SpherePdf};
 use crate::rtweekend::color::Color;
+use crate::rtweekend::sampler::{self, Dimension};
 use crate::rtweekend::vec3::ray::Ray;
 use crate::rtweekend::vec3::{
-    Point3, Vec3, dot, random_unit_vector, reflect, refract, unit_vector,
+    Point3, Vec3, dot, reflect, refract, sample_unit_sphere, unit_vector,
 };
-use crate::rtweekend::{PI, random_double, vec3};
+use crate::rtweekend::{PI, vec3};
 use std::sync::Arc;
 
 pub mod hittable;
 pub(crate) mod onb;
 pub(crate) mod texture;
 
+// Which depth limit of the camera a bounce counts against.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ScatterKind {
+    #[default]
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
 pub struct ScatterRecord {
     pub attenuation: Color,
     pub pdf_ptr: Arc<dyn Pdf>,
     pub skip_pdf: bool,
     pub skip_pdf_ray: Ray,
+    pub kind: ScatterKind,
 }
 
 impl ScatterRecord {
@@ -29,6 +40,7 @@ impl ScatterRecord {
             pdf_ptr: Arc::new(SpherePdf::default()),
             skip_pdf: bool::default(),
             skip_pdf_ray: Ray::default(),
+            kind: ScatterKind::Diffuse,
         }
     }
 }
@@ -135,12 +147,14 @@ impl Metal {
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
         let reflected = reflect(&r_in.direction, &rec.normal);
-        let reflected = unit_vector(&reflected) + (self.fuzz * random_unit_vector());
+        let (u, v) = sampler::sample_2d(Dimension::Bsdf);
+        let reflected = unit_vector(&reflected) + (self.fuzz * sample_unit_sphere(u, v));
 
         srec.attenuation = self.albedo.clone();
         srec.pdf_ptr = Arc::new(SpherePdf::default());
         srec.skip_pdf = true;
         srec.skip_pdf_ray = Ray::new_move(rec.p, reflected, r_in.time);
+        srec.kind = ScatterKind::Specular;
 
         true
     }
@@ -181,10 +195,14 @@ impl Material for Dielectric {
         let cannot_refract = ri * sin_theta > 1.0;
         let direction: vec3::Vec3;
 
-        if cannot_refract || Dielectric::reflectance(cos_theta, ri) > random_double() {
+        if cannot_refract
+            || Dielectric::reflectance(cos_theta, ri) > sampler::sample_1d(Dimension::Lobe)
+        {
             direction = reflect(&unit_direction, &rec.normal);
+            srec.kind = ScatterKind::Specular;
         } else {
             direction = refract(&unit_direction, &rec.normal, ri);
+            srec.kind = ScatterKind::Transmission;
         }
 
         srec.skip_pdf_ray = Ray::new_move(rec.p, direction, r_in.time);
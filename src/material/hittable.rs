@@ -2,9 +2,14 @@ mod aabb;
 pub(crate) mod bvh;
 pub(crate) mod constant_medium;
 pub(crate) mod hittable_list;
+pub(crate) mod instance;
+pub(crate) mod mesh;
+pub(crate) mod motion;
 pub(crate) mod quad;
 pub(crate) mod sphere;
+pub(crate) mod transform;
 pub(crate) mod triangle;
+pub(crate) mod triangle_mesh;
 
 use crate::material::hittable::aabb::AABB;
 use crate::material::{Lambertian, Material};
@@ -27,6 +32,8 @@ pub(crate) struct HitRecord {
     pub(crate) front_face: bool,
     pub tangent: Vec3,
     pub bitangent: Vec3,
+    // Index + 1 of the hit object in the outermost object list, 0 if no list was involved.
+    pub(crate) object_id: u32,
 }
 
 impl HitRecord {
@@ -41,6 +48,7 @@ impl HitRecord {
             front_face: false,
             tangent: Vec3::new(1.0, 0.0, 0.0),
             bitangent: Vec3::new(0.0, 1.0, 0.0),
+            object_id: 0,
         }
     }
     fn set_face_normal(
@@ -77,6 +85,9 @@ pub trait Hittable: Send + Sync {
     fn random(&self, origin: &Point3) -> Vec3 {
         Vec3::new(1.0, 0.0, 0.0)
     }
+
+    // Calls `f` with every material a hit on the object can report, always in the same order.
+    fn materials(&self, _f: &mut dyn FnMut(&Arc<dyn Material>)) {}
 }
 
 pub struct Translate {
@@ -111,6 +122,19 @@ impl Hittable for Translate {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.object.materials(f);
+    }
+
+    // Directions don't change under a translation, only the origin moves.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(&(*origin - self.offset), direction)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.object.random(&(*origin - self.offset))
+    }
 }
 
 pub struct RotateY {
@@ -161,37 +185,41 @@ impl RotateY {
     }
 }
 
+impl RotateY {
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+}
+
 impl Hittable for RotateY {
     fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
-        let origin = Point3::new(
-            self.cos_theta * r.origin.x - self.sin_theta * r.origin.z,
-            r.origin.y,
-            self.sin_theta * r.origin.x + self.cos_theta * r.origin.z,
+        let rotated_r = Ray::new_move(
+            self.to_object(&r.origin),
+            self.to_object(&r.direction),
+            r.time,
         );
 
-        let direction = Vec3::new(
-            self.cos_theta * r.direction.x - self.sin_theta * r.direction.z,
-            r.direction.y,
-            self.sin_theta * r.direction.x + self.cos_theta * r.direction.z,
-        );
-
-        let rotated_r = Ray::new_move(origin, direction, r.time);
-
         if !self.object.hit(&rotated_r, ray_t, rec) {
             return false;
         }
 
-        rec.p = Point3::new(
-            self.cos_theta * rec.p.x + self.sin_theta * rec.p.z,
-            rec.p.y,
-            -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z,
-        );
-
-        rec.normal = Vec3::new(
-            self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z,
-            rec.normal.y,
-            -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z,
-        );
+        rec.p = self.to_world(&rec.p);
+        rec.normal = self.to_world(&rec.normal);
+        // Normal maps are applied in this frame, so it has to turn with the object.
+        rec.tangent = self.to_world(&rec.tangent);
+        rec.bitangent = self.to_world(&rec.bitangent);
 
         true
     }
@@ -199,4 +227,18 @@ impl Hittable for RotateY {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.object.materials(f);
+    }
+
+    // A rotation keeps solid angles, so the density is the object's own.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object
+            .pdf_value(&self.to_object(origin), &self.to_object(direction))
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.to_world(&self.object.random(&self.to_object(origin)))
+    }
 }
@@ -0,0 +1,600 @@
+use crate::material::hittable::aabb::AABB;
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::vec3::Point3;
+use rayon::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Costs of a box test and of a primitive test relative to each other, for the surface area
+// heuristic.
+const TRAVERSAL_COST: f64 = 0.5;
+const INTERSECTION_COST: f64 = 1.0;
+// Buckets per axis of the binned split search.
+const BINS: usize = 16;
+// Nodes this deep are split at the median whatever the method, so trees over fewer than 2^32
+// primitives stay within the 64 levels of the traversal stack.
+const MEDIAN_DEPTH: usize = 32;
+// Nodes over this many primitives are binned, partitioned and split into subtrees on several
+// threads, for smaller ones that costs more than it saves.
+const PARALLEL_SIZE: usize = 4096;
+
+// How a node's primitives are divided between its children. Median halves them along the axis
+// where the centroids spread the most, the SAH methods minimize the estimated cost of tracing a
+// ray through the children, binned over a fixed set of candidate planes or swept over every
+// primitive.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) enum SplitMethod {
+    Median,
+    #[default]
+    BinnedSah,
+    SweepSah,
+}
+
+impl FromStr for SplitMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "median" => Ok(SplitMethod::Median),
+            "binned-sah" => Ok(SplitMethod::BinnedSah),
+            "sweep-sah" => Ok(SplitMethod::SweepSah),
+            _ => Err(format!(
+                "unknown BVH split \"{}\", expected median, binned-sah or sweep-sah",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct BvhOptions {
+    pub split: SplitMethod,
+    // Most primitives in a leaf. The SAH methods may stop splitting sooner when a leaf is
+    // cheaper than its children would be.
+    pub leaf_size: usize,
+    // Print the statistics of every tree built.
+    pub stats: bool,
+}
+
+impl Default for BvhOptions {
+    fn default() -> Self {
+        BvhOptions {
+            split: SplitMethod::default(),
+            leaf_size: 4,
+            stats: false,
+        }
+    }
+}
+
+static OPTIONS: OnceLock<BvhOptions> = OnceLock::new();
+// Nanoseconds spent in build so far.
+static BUILD_TIME: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn set_options(options: BvhOptions) {
+    // Must be called before the scene is built to take effect on every tree.
+    let _ = OPTIONS.set(options);
+}
+
+pub(crate) fn options() -> BvhOptions {
+    *OPTIONS.get_or_init(BvhOptions::default)
+}
+
+// Time taken by all trees built so far.
+pub(crate) fn build_time() -> Duration {
+    Duration::from_nanos(BUILD_TIME.load(Ordering::Relaxed))
+}
+
+pub(crate) enum BuildNode {
+    // Covers order[start..start + count] of the primitives, see build.
+    Leaf {
+        bbox: AABB,
+        start: usize,
+        count: usize,
+    },
+    Interior {
+        bbox: AABB,
+        // Along which the children were split.
+        axis: usize,
+        children: Box<[BuildNode; 2]>,
+    },
+}
+
+struct Primitive {
+    bbox: AABB,
+    centroid: Point3,
+}
+
+// Builds a tree over primitives with the given boxes using the options set for the run. The
+// returned order lists the primitives so that every leaf covers a contiguous range of it.
+pub(crate) fn build(boxes: &[AABB]) -> (BuildNode, Vec<usize>) {
+    build_with(boxes, &options())
+}
+
+pub(crate) fn build_with(boxes: &[AABB], options: &BvhOptions) -> (BuildNode, Vec<usize>) {
+    let start = Instant::now();
+    let primitives: Vec<Primitive> = boxes
+        .par_iter()
+        .map(|bbox| Primitive {
+            bbox: *bbox,
+            centroid: Point3::new(
+                (bbox.x.min + bbox.x.max) / 2.0,
+                (bbox.y.min + bbox.y.max) / 2.0,
+                (bbox.z.min + bbox.z.max) / 2.0,
+            ),
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..boxes.len()).collect();
+    let root = build_node(&primitives, &mut order, 0, 0, options);
+
+    let elapsed = start.elapsed();
+    BUILD_TIME.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    if options.stats {
+        println!(
+            "BVH over {} primitives built in {:.3}s: {}",
+            boxes.len(),
+            elapsed.as_secs_f64(),
+            root.stats()
+        );
+    }
+    (root, order)
+}
+
+fn build_node(
+    primitives: &[Primitive],
+    order: &mut [usize],
+    start: usize,
+    depth: usize,
+    options: &BvhOptions,
+) -> BuildNode {
+    let count = order.len();
+    let (bbox, centroids) = if count >= PARALLEL_SIZE {
+        order
+            .par_iter()
+            .fold(
+                || (AABB::EMPTY, AABB::EMPTY),
+                |b, &i| add_bounds(b, &primitives[i]),
+            )
+            .reduce(|| (AABB::EMPTY, AABB::EMPTY), merge_bounds)
+    } else {
+        order.iter().fold((AABB::EMPTY, AABB::EMPTY), |b, &i| {
+            add_bounds(b, &primitives[i])
+        })
+    };
+
+    let leaf = BuildNode::Leaf { bbox, start, count };
+    if count <= 1 {
+        return leaf;
+    }
+
+    let split = if depth >= MEDIAN_DEPTH {
+        SplitMethod::Median
+    } else {
+        options.split
+    };
+    let (mid, axis) = match split {
+        SplitMethod::Median if count <= options.leaf_size => return leaf,
+        SplitMethod::Median => split_median(primitives, order, &centroids),
+        SplitMethod::BinnedSah | SplitMethod::SweepSah => {
+            let split = if split == SplitMethod::BinnedSah {
+                split_binned(primitives, order, &bbox, &centroids)
+            } else {
+                split_sweep(primitives, order, &bbox)
+            };
+            match split {
+                Some((cost, ..)) if count <= options.leaf_size && cost >= leaf_cost(count) => {
+                    return leaf;
+                }
+                Some((_, mid, axis)) => (mid, axis),
+                // The centroids all coincide, the primitives can't be told apart by position.
+                None if count <= options.leaf_size => return leaf,
+                None => split_median(primitives, order, &centroids),
+            }
+        }
+    };
+
+    let (left, right) = order.split_at_mut(mid);
+    let children = if count >= PARALLEL_SIZE {
+        rayon::join(
+            || build_node(primitives, left, start, depth + 1, options),
+            || build_node(primitives, right, start + mid, depth + 1, options),
+        )
+    } else {
+        (
+            build_node(primitives, left, start, depth + 1, options),
+            build_node(primitives, right, start + mid, depth + 1, options),
+        )
+    };
+    BuildNode::Interior {
+        bbox,
+        axis,
+        children: Box::new([children.0, children.1]),
+    }
+}
+
+// Box of the primitives and box of their centroids, with another primitive.
+fn add_bounds((bbox, centroids): (AABB, AABB), p: &Primitive) -> (AABB, AABB) {
+    // Built directly, new_points would pad the point to a small box.
+    let c = p.centroid;
+    let point = AABB {
+        x: Interval::new(c.x, c.x),
+        y: Interval::new(c.y, c.y),
+        z: Interval::new(c.z, c.z),
+    };
+    (
+        AABB::new_merge(&bbox, &p.bbox),
+        AABB::new_merge(&centroids, &point),
+    )
+}
+
+fn merge_bounds(a: (AABB, AABB), b: (AABB, AABB)) -> (AABB, AABB) {
+    (AABB::new_merge(&a.0, &b.0), AABB::new_merge(&a.1, &b.1))
+}
+
+fn leaf_cost(count: usize) -> f64 {
+    count as f64 * INTERSECTION_COST
+}
+
+// Cost of a node whose children hold `left` and `right` primitives in the given surface areas,
+// relative to the area of the node.
+fn split_cost(area: f64, left: (f64, usize), right: (f64, usize)) -> f64 {
+    TRAVERSAL_COST + (left.0 * left.1 as f64 + right.0 * right.1 as f64) / area * INTERSECTION_COST
+}
+
+fn surface_area(bbox: &AABB) -> f64 {
+    let (x, y, z) = (bbox.x.size(), bbox.y.size(), bbox.z.size());
+    2.0 * (x * y + y * z + z * x)
+}
+
+// Splits at the median centroid along the axis where the centroids spread the most. Returns the
+// size of the left side and the axis.
+fn split_median(primitives: &[Primitive], order: &mut [usize], centroids: &AABB) -> (usize, usize) {
+    let axis = centroids.longest_axis() as i32;
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |&a, &b| {
+        let a = primitives[a].centroid.index_val(axis);
+        let b = primitives[b].centroid.index_val(axis);
+        a.total_cmp(b)
+    });
+    (mid, axis as usize)
+}
+
+// Sorts the centroids into buckets along every axis and tries the planes between the buckets.
+// Returns the cost of the best split, the size of its left side and its axis, None when no axis
+// can be split.
+fn split_binned(
+    primitives: &[Primitive],
+    order: &mut [usize],
+    bbox: &AABB,
+    centroids: &AABB,
+) -> Option<(f64, usize, usize)> {
+    let area = surface_area(bbox);
+    let bin_of = |i: usize, axis: i32| {
+        let extent = centroids.axis_interval(axis);
+        let offset = (primitives[i].centroid.index_val(axis) - extent.min) / extent.size();
+        ((offset * BINS as f64) as usize).min(BINS - 1)
+    };
+
+    // Boxes and counts of the buckets along every axis, a flat axis puts everything in its
+    // first bucket.
+    let fill = |order: &[usize]| {
+        let mut bins = [[(AABB::EMPTY, 0); BINS]; 3];
+        for &i in order {
+            for (axis, bins) in bins.iter_mut().enumerate() {
+                let bin = &mut bins[bin_of(i, axis as i32)];
+                bin.0 = AABB::new_merge(&bin.0, &primitives[i].bbox);
+                bin.1 += 1;
+            }
+        }
+        bins
+    };
+    let all_bins = if order.len() >= PARALLEL_SIZE {
+        order
+            .par_chunks(PARALLEL_SIZE)
+            .map(fill)
+            .reduce_with(|mut a, b| {
+                for (a, b) in a.iter_mut().flatten().zip(b.iter().flatten()) {
+                    *a = (AABB::new_merge(&a.0, &b.0), a.1 + b.1);
+                }
+                a
+            })
+            .unwrap()
+    } else {
+        fill(order)
+    };
+
+    let mut best: Option<(f64, i32, usize)> = None;
+    for axis in 0..3 {
+        if centroids.axis_interval(axis).size() <= 0.0 {
+            continue;
+        }
+        let bins = &all_bins[axis as usize];
+
+        // Areas and counts to the right of every plane, then swept from the left.
+        let mut right = [(0.0, 0usize); BINS];
+        let (mut right_box, mut right_count) = (AABB::EMPTY, 0);
+        for b in (1..BINS).rev() {
+            right_box = AABB::new_merge(&right_box, &bins[b].0);
+            right_count += bins[b].1;
+            right[b] = (surface_area(&right_box), right_count);
+        }
+        let (mut left_box, mut left_count) = (AABB::EMPTY, 0);
+        for b in 1..BINS {
+            left_box = AABB::new_merge(&left_box, &bins[b - 1].0);
+            left_count += bins[b - 1].1;
+            if left_count == 0 || right[b].1 == 0 {
+                continue;
+            }
+            let cost = split_cost(area, (surface_area(&left_box), left_count), right[b]);
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, b));
+            }
+        }
+    }
+
+    let (cost, axis, plane) = best?;
+    let mid = if order.len() >= PARALLEL_SIZE {
+        let (left, right): (Vec<usize>, Vec<usize>) =
+            order.par_iter().partition(|&&i| bin_of(i, axis) < plane);
+        order[..left.len()].copy_from_slice(&left);
+        order[left.len()..].copy_from_slice(&right);
+        left.len()
+    } else {
+        partition(order, |i| bin_of(i, axis) < plane)
+    };
+    Some((cost, mid, axis as usize))
+}
+
+// Sorts the primitives by centroid along every axis and tries splitting between each
+// neighbouring pair. Returns the cost of the best split, the size of its left side and its axis.
+fn split_sweep(
+    primitives: &[Primitive],
+    order: &mut [usize],
+    bbox: &AABB,
+) -> Option<(f64, usize, usize)> {
+    let area = surface_area(bbox);
+    let count = order.len();
+    let sort = |order: &mut [usize], axis: i32| {
+        let compare = |&a: &usize, &b: &usize| {
+            let a = primitives[a].centroid.index_val(axis);
+            let b = primitives[b].centroid.index_val(axis);
+            a.total_cmp(b)
+        };
+        if count >= PARALLEL_SIZE {
+            order.par_sort_unstable_by(compare);
+        } else {
+            order.sort_unstable_by(compare);
+        }
+    };
+
+    let mut best: Option<(f64, i32, usize)> = None;
+    let mut right_areas = vec![0.0; count];
+    for axis in 0..3 {
+        sort(order, axis);
+        let mut right_box = AABB::EMPTY;
+        for mid in (1..count).rev() {
+            right_box = AABB::new_merge(&right_box, &primitives[order[mid]].bbox);
+            right_areas[mid] = surface_area(&right_box);
+        }
+        let mut left_box = AABB::EMPTY;
+        for mid in 1..count {
+            left_box = AABB::new_merge(&left_box, &primitives[order[mid - 1]].bbox);
+            let cost = split_cost(
+                area,
+                (surface_area(&left_box), mid),
+                (right_areas[mid], count - mid),
+            );
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, mid));
+            }
+        }
+    }
+
+    let (cost, axis, mid) = best?;
+    sort(order, axis);
+    Some((cost, mid, axis as usize))
+}
+
+// Moves the primitives for which `left` holds to the front, returns how many there are.
+fn partition(order: &mut [usize], left: impl Fn(usize) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..order.len() {
+        if left(order[i]) {
+            order.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+pub(crate) struct BvhStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub depth: usize,
+    // Expected cost of tracing a ray that hits the root box, in primitive tests.
+    pub sah_cost: f64,
+}
+
+impl BuildNode {
+    pub(crate) fn bbox(&self) -> AABB {
+        match self {
+            BuildNode::Leaf { bbox, .. } | BuildNode::Interior { bbox, .. } => *bbox,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            nodes: 0,
+            leaves: 0,
+            depth: 0,
+            sah_cost: 0.0,
+        };
+        let root_area = surface_area(&self.bbox());
+        let mut stack = vec![(self, 1)];
+        while let Some((node, depth)) = stack.pop() {
+            stats.nodes += 1;
+            stats.depth = stats.depth.max(depth);
+            // Chance that a ray through the root also passes this node.
+            let share = if root_area > 0.0 {
+                surface_area(&node.bbox()) / root_area
+            } else {
+                1.0
+            };
+            match node {
+                BuildNode::Leaf { count, .. } => {
+                    stats.leaves += 1;
+                    stats.sah_cost += share * leaf_cost(*count);
+                }
+                BuildNode::Interior { children, .. } => {
+                    stats.sah_cost += share * TRAVERSAL_COST;
+                    stack.extend(children.iter().map(|child| (child, depth + 1)));
+                }
+            }
+        }
+        stats
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes ({} leaves), depth {}, SAH cost {:.2}",
+            self.nodes, self.leaves, self.depth, self.sah_cost
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    const METHODS: [SplitMethod; 3] = [
+        SplitMethod::Median,
+        SplitMethod::BinnedSah,
+        SplitMethod::SweepSah,
+    ];
+
+    fn random_boxes(n: usize, seed: u64) -> Vec<AABB> {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        (0..n)
+            .map(|_| {
+                let c = Point3::new(
+                    rng.random_range(-10.0..10.0),
+                    rng.random_range(-10.0..10.0),
+                    rng.random_range(-10.0..10.0),
+                );
+                let h = rng.random_range(0.01..1.0);
+                AABB::new_points(c - Point3::new(h, h, h), c + Point3::new(h, h, h))
+            })
+            .collect()
+    }
+
+    fn options(split: SplitMethod, leaf_size: usize) -> BvhOptions {
+        BvhOptions {
+            split,
+            leaf_size,
+            stats: false,
+        }
+    }
+
+    fn contains(outer: &AABB, inner: &AABB) -> bool {
+        [(outer.x, inner.x), (outer.y, inner.y), (outer.z, inner.z)]
+            .iter()
+            .all(|(o, i)| o.min <= i.min && i.max <= o.max)
+    }
+
+    // Checks that `order` lists every primitive once, that the leaves cover it in order with at
+    // most `leaf_size` primitives each, and that every box holds what is below it. Returns the
+    // depth of the tree.
+    fn check(boxes: &[AABB], leaf_size: usize, root: &BuildNode, order: &[usize]) -> usize {
+        let mut seen = vec![false; boxes.len()];
+        assert_eq!(order.len(), boxes.len());
+        for &i in order {
+            assert!(!seen[i], "primitive {} listed twice", i);
+            seen[i] = true;
+        }
+
+        let mut next = 0;
+        let mut depth = 0;
+        let mut stack = vec![(root, 1)];
+        while let Some((node, level)) = stack.pop() {
+            depth = depth.max(level);
+            match node {
+                BuildNode::Leaf { bbox, start, count } => {
+                    assert_eq!(*start, next);
+                    assert!(*count <= leaf_size.max(1), "leaf of {} primitives", count);
+                    for &i in &order[*start..*start + *count] {
+                        assert!(contains(bbox, &boxes[i]));
+                    }
+                    next += count;
+                }
+                BuildNode::Interior { bbox, children, .. } => {
+                    for child in children.iter() {
+                        assert!(contains(bbox, &child.bbox()));
+                    }
+                    stack.push((&children[1], level + 1));
+                    stack.push((&children[0], level + 1));
+                }
+            }
+        }
+        assert_eq!(next, boxes.len());
+        depth
+    }
+
+    #[test]
+    fn leaves_cover_every_primitive_once() {
+        let same = AABB::new_points(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let flat: Vec<AABB> = random_boxes(200, 7)
+            .iter()
+            .map(|b| AABB::new(b.x, b.y, Interval::new(0.0, 0.0)))
+            .collect();
+        let sets = [
+            Vec::new(),
+            random_boxes(1, 1),
+            random_boxes(3, 2),
+            random_boxes(1000, 3),
+            vec![same; 40],
+            flat,
+        ];
+        for split in METHODS {
+            for leaf_size in [1, 4, 7] {
+                for boxes in &sets {
+                    let (root, order) = build_with(boxes, &options(split, leaf_size));
+                    check(boxes, leaf_size, &root, &order);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_build_keeps_the_invariants() {
+        let boxes = random_boxes(3 * PARALLEL_SIZE, 11);
+        for split in METHODS {
+            let (root, order) = build_with(&boxes, &options(split, 4));
+            check(&boxes, 4, &root, &order);
+        }
+    }
+
+    #[test]
+    fn skewed_trees_fit_the_traversal_stack() {
+        // Cubes from the origin, each twice the size of the one before. The SAH splits only a
+        // few of the largest off at a time, which without the median levels below MEDIAN_DEPTH
+        // makes the tree over a hundred levels deep.
+        let boxes: Vec<AABB> = (0..400)
+            .map(|i| {
+                let size = 2f64.powi(i);
+                AABB::new_points(Point3::new(0.0, 0.0, 0.0), Point3::new(size, size, size))
+            })
+            .collect();
+        for split in METHODS {
+            let (root, order) = build_with(&boxes, &options(split, 1));
+            let depth = check(&boxes, 1, &root, &order);
+            assert!(depth <= 64, "depth {}", depth);
+        }
+    }
+}
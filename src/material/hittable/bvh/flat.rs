@@ -0,0 +1,300 @@
+use crate::material::hittable::aabb::AABB;
+use crate::material::hittable::bvh::build::BuildNode;
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::vec3::ray::Ray;
+use std::ops::Range;
+
+// Deep enough for any tree from build, see MEDIAN_DEPTH there.
+const STACK_SIZE: usize = 64;
+
+// A built tree laid out depth first in one array. The first child of an interior node is the
+// node right after it, so only the second child needs an index.
+pub(crate) struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    bbox: AABB,
+}
+
+struct FlatNode {
+    // Box minimum and maximum, indexed by the sign of the ray direction in the slab test.
+    bounds: [[f64; 3]; 2],
+    // Leaves: the first of their primitives. Interior nodes: the second child.
+    offset: u32,
+    // Primitives in a leaf, 0 for interior nodes.
+    count: u32,
+    // Split axis of interior nodes.
+    axis: u8,
+}
+
+// The ray with what every box test needs precomputed.
+struct RayBoxes {
+    origin: [f64; 3],
+    inverse: [f64; 3],
+    negative: [usize; 3],
+}
+
+impl FlatBvh {
+    pub(crate) fn new(root: BuildNode) -> FlatBvh {
+        let mut bvh = FlatBvh {
+            nodes: Vec::new(),
+            bbox: root.bbox(),
+        };
+        // A tree over nothing is a single empty leaf, it's left out so it isn't read as an
+        // interior node.
+        if !matches!(root, BuildNode::Leaf { count: 0, .. }) {
+            bvh.flatten(root);
+        }
+        bvh
+    }
+
+    fn flatten(&mut self, node: BuildNode) {
+        let index = self.nodes.len();
+        let bbox = node.bbox();
+        self.nodes.push(FlatNode {
+            bounds: [
+                [bbox.x.min, bbox.y.min, bbox.z.min],
+                [bbox.x.max, bbox.y.max, bbox.z.max],
+            ],
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+        match node {
+            BuildNode::Leaf { start, count, .. } => {
+                self.nodes[index].offset = start as u32;
+                self.nodes[index].count = count as u32;
+            }
+            BuildNode::Interior { axis, children, .. } => {
+                let [left, right] = *children;
+                self.flatten(left);
+                self.nodes[index].offset = self.nodes.len() as u32;
+                self.nodes[index].axis = axis as u8;
+                self.flatten(right);
+            }
+        }
+    }
+
+    pub(crate) fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    // Walks the leaves whose boxes the ray passes within ray_t, the child on the near side of a
+    // split first. `leaf` tests the primitives in the given range, lowers the interval's max to
+    // the closest hit and says whether it found one. Returns whether any leaf did.
+    pub(crate) fn traverse(
+        &self,
+        r: &Ray,
+        ray_t: Interval,
+        mut leaf: impl FnMut(Range<usize>, &mut Interval) -> bool,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let d = r.direction;
+        let ray = RayBoxes {
+            origin: [r.origin.x, r.origin.y, r.origin.z],
+            inverse: [1.0 / d.x, 1.0 / d.y, 1.0 / d.z],
+            negative: [
+                (d.x < 0.0) as usize,
+                (d.y < 0.0) as usize,
+                (d.z < 0.0) as usize,
+            ],
+        };
+
+        let mut closest = ray_t;
+        let mut hit_anything = false;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut top = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            if ray.hits(node, &closest) {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    if leaf(start..start + node.count as usize, &mut closest) {
+                        hit_anything = true;
+                    }
+                } else if ray.negative[node.axis as usize] == 1 {
+                    stack[top] = index as u32 + 1;
+                    top += 1;
+                    index = node.offset as usize;
+                    continue;
+                } else {
+                    stack[top] = node.offset;
+                    top += 1;
+                    index += 1;
+                    continue;
+                }
+            }
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            index = stack[top] as usize;
+        }
+        hit_anything
+    }
+}
+
+impl RayBoxes {
+    // Slab test, the same as AABB::hit.
+    fn hits(&self, node: &FlatNode, ray_t: &Interval) -> bool {
+        let (mut t_min, mut t_max) = (ray_t.min, ray_t.max);
+        for axis in 0..3 {
+            let near = node.bounds[self.negative[axis]][axis];
+            let far = node.bounds[1 - self.negative[axis]][axis];
+            t_min = t_min.max((near - self.origin[axis]) * self.inverse[axis]);
+            t_max = t_max.min((far - self.origin[axis]) * self.inverse[axis]);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::hittable::bvh::build::{self, BvhOptions, SplitMethod};
+    use crate::rtweekend::vec3::{Point3, Vec3, dot};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    struct Ball {
+        center: Point3,
+        radius: f64,
+    }
+
+    impl Ball {
+        fn bbox(&self) -> AABB {
+            let r = Vec3::new(self.radius, self.radius, self.radius);
+            AABB::new_points(self.center - r, self.center + r)
+        }
+
+        // Nearest t within ray_t where the ray enters or leaves the ball.
+        fn hit(&self, r: &Ray, ray_t: &Interval) -> Option<f64> {
+            let oc = self.center - r.origin;
+            let a = r.direction.length_squared();
+            let h = dot(&r.direction, &oc);
+            let c = oc.length_squared() - self.radius * self.radius;
+            let discriminant = h * h - a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let sqrtd = discriminant.sqrt();
+            [(h - sqrtd) / a, (h + sqrtd) / a]
+                .into_iter()
+                .find(|&t| ray_t.surrounds(t))
+        }
+    }
+
+    fn point(rng: &mut SmallRng, extent: f64) -> Point3 {
+        Point3::new(
+            rng.random_range(-extent..extent),
+            rng.random_range(-extent..extent),
+            rng.random_range(-extent..extent),
+        )
+    }
+
+    // Balls in `order` of the build, so the leaf ranges index them directly.
+    fn scene(seed: u64, n: usize, options: &BvhOptions) -> (Vec<Ball>, FlatBvh) {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let balls: Vec<Ball> = (0..n)
+            .map(|_| Ball {
+                center: point(&mut rng, 10.0),
+                radius: rng.random_range(0.05..1.5),
+            })
+            .collect();
+        let boxes: Vec<AABB> = balls.iter().map(Ball::bbox).collect();
+        let (root, order) = build::build_with(&boxes, options);
+        let mut balls: Vec<Option<Ball>> = balls.into_iter().map(Some).collect();
+        let balls = order.iter().map(|&i| balls[i].take().unwrap()).collect();
+        (balls, FlatBvh::new(root))
+    }
+
+    fn closest_linear(balls: &[Ball], r: &Ray, ray_t: Interval) -> Option<(usize, f64)> {
+        let mut closest = ray_t;
+        let mut found = None;
+        for (i, ball) in balls.iter().enumerate() {
+            if let Some(t) = ball.hit(r, &closest) {
+                closest.max = t;
+                found = Some((i, t));
+            }
+        }
+        found
+    }
+
+    fn closest_traversed(
+        bvh: &FlatBvh,
+        balls: &[Ball],
+        r: &Ray,
+        ray_t: Interval,
+    ) -> Option<(usize, f64)> {
+        let mut found = None;
+        let hit = bvh.traverse(r, ray_t, |leaf, closest| {
+            let mut hit_anything = false;
+            for i in leaf {
+                if let Some(t) = balls[i].hit(r, closest) {
+                    closest.max = t;
+                    found = Some((i, t));
+                    hit_anything = true;
+                }
+            }
+            hit_anything
+        });
+        assert_eq!(hit, found.is_some());
+        found
+    }
+
+    #[test]
+    fn traversal_finds_the_closest_hit() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        // Axis aligned directions make the inverse direction infinite on two axes.
+        let axes = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        for split in [
+            SplitMethod::Median,
+            SplitMethod::BinnedSah,
+            SplitMethod::SweepSah,
+        ] {
+            for leaf_size in [1, 4] {
+                let options = BvhOptions {
+                    split,
+                    leaf_size,
+                    stats: false,
+                };
+                let (balls, bvh) = scene(leaf_size as u64, 500, &options);
+                for k in 0..2000 {
+                    let origin = point(&mut rng, 15.0);
+                    let direction = if k % 10 == 0 {
+                        axes[k / 10 % 3]
+                    } else {
+                        point(&mut rng, 1.0)
+                    };
+                    let r = Ray::new(origin, direction);
+                    let ray_t = Interval::new(0.001, if k % 4 == 0 { 5.0 } else { f64::INFINITY });
+                    assert_eq!(
+                        closest_traversed(&bvh, &balls, &r, ray_t),
+                        closest_linear(&balls, &r, ray_t)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tree_is_never_hit() {
+        let (root, _) = build::build_with(&[], &BvhOptions::default());
+        let bvh = FlatBvh::new(root);
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 1.0, 1.0));
+        assert!(
+            !bvh.traverse(&r, Interval::new(0.0, f64::INFINITY), |_, _| {
+                panic!("no leaves to visit")
+            })
+        );
+    }
+}
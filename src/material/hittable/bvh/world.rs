@@ -0,0 +1,259 @@
+use crate::material::Material;
+use crate::material::hittable::aabb::AABB;
+use crate::material::hittable::bvh::BvhNode;
+use crate::material::hittable::hittable_list::HittableList;
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::vec3::ray::Ray;
+use crate::rtweekend::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+// Objects whose box has a diagonal this many times longer than the box around the centroids of
+// all objects are left out of the tree.
+const HUGE_SCALE: f64 = 2.0;
+
+// The top level of the scene, a BVH over its objects, which are models with BVHs of their own
+// or simple shapes. Objects that enclose much of the scene, like a background sphere, or have
+// no finite box would stretch the nodes they end up in, so they are tested one by one instead.
+// Object ids are the objects' indices in the list plus one, as with HittableList.
+pub(crate) struct World {
+    tree: BvhNode,
+    huge: Vec<(u32, Arc<dyn Hittable>)>,
+    bbox: AABB,
+}
+
+impl World {
+    pub(crate) fn new(objects: HittableList) -> World {
+        let bbox = objects.bounding_box();
+        let objects: Vec<(u32, Arc<dyn Hittable>)> = objects
+            .objects
+            .into_iter()
+            .enumerate()
+            .map(|(index, object)| (index as u32 + 1, object))
+            .collect();
+
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        let mut diagonals = Vec::new();
+        for (_, object) in &objects {
+            let bbox = object.bounding_box();
+            if let Some(c) = centroid(&bbox) {
+                min = Point3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z));
+                max = Point3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z));
+                diagonals.push(diagonal(&bbox).length());
+            }
+        }
+        let mut limit = HUGE_SCALE * (max - min).length();
+        // When most objects are larger than that, the centroids are too close together to tell
+        // what is huge, e.g. with a single object or objects around one point. Then only the
+        // unbounded ones are left out.
+        if 2 * diagonals.iter().filter(|&&d| d > limit).count() > diagonals.len() {
+            limit = f64::INFINITY;
+        }
+
+        let (huge, bounded): (Vec<_>, Vec<_>) = objects.into_iter().partition(|(_, object)| {
+            let bbox = object.bounding_box();
+            centroid(&bbox).is_none() || diagonal(&bbox).length() > limit
+        });
+        World {
+            tree: BvhNode::new(bounded),
+            huge,
+            bbox,
+        }
+    }
+}
+
+fn diagonal(bbox: &AABB) -> Vec3 {
+    Vec3::new(bbox.x.size(), bbox.y.size(), bbox.z.size())
+}
+
+// None for boxes that are empty or reach to infinity.
+fn centroid(bbox: &AABB) -> Option<Point3> {
+    let d = diagonal(bbox);
+    (d.x.is_finite() && d.y.is_finite() && d.z.is_finite()).then(|| {
+        Point3::new(
+            (bbox.x.min + bbox.x.max) / 2.0,
+            (bbox.y.min + bbox.y.max) / 2.0,
+            (bbox.z.min + bbox.z.max) / 2.0,
+        )
+    })
+}
+
+impl Hittable for World {
+    fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
+        let mut hit_anything = self.tree.hit(r, ray_t, rec);
+        let mut closest = if hit_anything { rec.t } else { ray_t.max };
+        for (id, object) in &self.huge {
+            if object.hit(r, &mut Interval::new(ray_t.min, closest), rec) {
+                hit_anything = true;
+                closest = rec.t;
+                rec.object_id = *id;
+            }
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        // In the order of the scene's object list rather than the tree's.
+        let mut objects: Vec<_> = self.tree.objects.iter().chain(&self.huge).collect();
+        objects.sort_by_key(|(id, _)| *id);
+        for (_, object) in objects {
+            object.materials(f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::hittable::sphere::Sphere;
+    use crate::material::{Lambertian, Material};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    // The plane y = 0, its box reaches to infinity.
+    struct Floor {
+        mat: Arc<dyn Material>,
+    }
+
+    impl Hittable for Floor {
+        fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
+            let t = -r.origin.y / r.direction.y;
+            if !ray_t.surrounds(t) {
+                return false;
+            }
+            rec.t = t;
+            rec.p = r.at(t);
+            rec.normal = Vec3::new(0.0, 1.0, 0.0);
+            rec.mat = self.mat.clone();
+            true
+        }
+
+        fn bounding_box(&self) -> AABB {
+            AABB::new(
+                Interval::UNIVERSE,
+                Interval::new(-0.001, 0.001),
+                Interval::UNIVERSE,
+            )
+        }
+    }
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::default())
+    }
+
+    fn sphere(center: Point3, radius: f64) -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(center, radius, material()))
+    }
+
+    fn point(rng: &mut SmallRng, extent: f64) -> Point3 {
+        Point3::new(
+            rng.random_range(-extent..extent),
+            rng.random_range(-extent..extent),
+            rng.random_range(-extent..extent),
+        )
+    }
+
+    // A background sphere and a floor around small spheres, as in the showcase scene.
+    fn scene(seed: u64) -> HittableList {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut list = HittableList::new();
+        list.add(sphere(Point3::default(), 2000.0));
+        list.add(Arc::new(Floor { mat: material() }));
+        for _ in 0..300 {
+            let radius = rng.random_range(0.05..2.0);
+            list.add(sphere(point(&mut rng, 50.0), radius));
+        }
+        list
+    }
+
+    fn huge_ids(world: &World) -> Vec<u32> {
+        world.huge.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn huge_and_unbounded_objects_stay_out_of_the_tree() {
+        let world = World::new(scene(1));
+        assert_eq!(huge_ids(&world), [1, 2]);
+        assert_eq!(world.tree.objects.len(), 300);
+    }
+
+    #[test]
+    fn objects_count_as_huge_past_the_scale() {
+        // Centroids 10 apart on x, so objects with a diagonal over 20 are huge.
+        let limit = HUGE_SCALE * 10.0;
+        for (radius, huge) in [(0.99, false), (1.01, true)] {
+            let mut list = HittableList::new();
+            list.add(sphere(Point3::new(-5.0, 0.0, 0.0), 1.0));
+            list.add(sphere(Point3::new(5.0, 0.0, 0.0), 1.0));
+            list.add(sphere(
+                Point3::default(),
+                radius * limit / (2.0 * 3f64.sqrt()),
+            ));
+            let world = World::new(list);
+            assert_eq!(huge_ids(&world), if huge { vec![3] } else { vec![] });
+        }
+    }
+
+    #[test]
+    fn objects_stay_in_the_tree_without_a_centroid_spread() {
+        let mut single = HittableList::new();
+        single.add(sphere(Point3::new(1.0, 2.0, 3.0), 1.0));
+        single.add(Arc::new(Floor { mat: material() }));
+        let world = World::new(single);
+        assert_eq!(huge_ids(&world), [2]);
+        assert_eq!(world.tree.objects.len(), 1);
+
+        // Around one point, and nearly so, most objects would count as huge.
+        for offset in [0.0, 0.01] {
+            let mut nested = HittableList::new();
+            for (i, radius) in [1.0, 2.0, 5.0, 20.0].into_iter().enumerate() {
+                nested.add(sphere(Point3::new(offset * i as f64, 0.0, 0.0), radius));
+            }
+            let world = World::new(nested);
+            assert!(world.huge.is_empty());
+            assert_eq!(world.tree.objects.len(), 4);
+        }
+    }
+
+    #[test]
+    fn hits_match_a_linear_search() {
+        let list = scene(2);
+        let mut linear = HittableList::new();
+        for object in &list.objects {
+            linear.add(object.clone());
+        }
+        let centers: Vec<Point3> = list.objects[2..]
+            .iter()
+            .filter_map(|object| centroid(&object.bounding_box()))
+            .collect();
+        let world = World::new(list);
+
+        // Half of the rays aim at a small sphere, the others anywhere.
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut hits = 0;
+        for i in 0..2000 {
+            let origin = point(&mut rng, 60.0);
+            let target = if i % 2 == 0 {
+                centers[rng.random_range(0..centers.len())]
+            } else {
+                point(&mut rng, 50.0)
+            };
+            let r = Ray::new(origin, target - origin);
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+
+            let (mut a, mut b) = (HitRecord::new(), HitRecord::new());
+            assert!(world.hit(&r, &mut ray_t.clone(), &mut a));
+            assert!(linear.hit(&r, &mut ray_t.clone(), &mut b));
+            assert_eq!(a.t, b.t);
+            assert_eq!(a.object_id, b.object_id);
+            hits += (a.object_id > 2) as u32;
+        }
+        // The floor blocks some of the aimed rays.
+        assert!(hits > 400, "{} hits on small spheres", hits);
+    }
+}
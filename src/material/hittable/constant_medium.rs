@@ -4,7 +4,7 @@ use crate::material::texture::Texture;
 use crate::material::{Isotropic, Material};
 use crate::rtweekend::color::Color;
 use crate::rtweekend::interval::Interval;
-use crate::rtweekend::random_double;
+use crate::rtweekend::sampler::{self, Dimension};
 use crate::rtweekend::vec3::Vec3;
 use crate::rtweekend::vec3::ray::Ray;
 use std::sync::Arc;
@@ -70,7 +70,7 @@ impl Hittable for ConstantMedium {
 
         let ray_length = r.direction.length();
         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random_double().ln();
+        let hit_distance = self.neg_inv_density * sampler::sample_1d(Dimension::Medium).ln();
 
         if hit_distance > distance_inside_boundary {
             return false;
@@ -89,4 +89,8 @@ impl Hittable for ConstantMedium {
     fn bounding_box(&self) -> AABB {
         self.boundary.bounding_box()
     }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        f(&self.phase_function);
+    }
 }
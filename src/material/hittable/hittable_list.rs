@@ -1,7 +1,8 @@
+use crate::material::Material;
 use crate::material::hittable::aabb::AABB;
 use crate::material::hittable::{HitRecord, Hittable};
 use crate::rtweekend::interval::Interval;
-use crate::rtweekend::random_int_range;
+use crate::rtweekend::sampler;
 use crate::rtweekend::vec3::Vec3;
 use crate::rtweekend::vec3::ray::Ray;
 use std::sync::Arc;
@@ -36,7 +37,7 @@ impl Hittable for HittableList {
         let mut hit_anything = false;
         let mut closest_so_far = t.max;
 
-        for object in &self.objects {
+        for (index, object) in self.objects.iter().enumerate() {
             if object.hit(
                 &ray,
                 &mut Interval::new(t.min, closest_so_far),
@@ -45,6 +46,8 @@ impl Hittable for HittableList {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
+                // Lists further out overwrite this again, so the outermost index is kept.
+                rec.object_id = index as u32 + 1;
             }
         }
 
@@ -55,6 +58,12 @@ impl Hittable for HittableList {
         self.bbox.clone()
     }
 
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        for object in &self.objects {
+            object.materials(f);
+        }
+    }
+
     fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
         let weight = 1.0 / self.objects.len() as f64;
         let mut sum = 0.0;
@@ -67,7 +76,8 @@ impl Hittable for HittableList {
     }
 
     fn random(&self, origin: &Vec3) -> Vec3 {
-        let int_size = self.objects.len() as i32;
-        self.objects[random_int_range(0, int_size - 1) as usize].random(origin)
+        sampler::select_light(self.objects.len(), |index| {
+            self.objects[index].random(origin)
+        })
     }
 }
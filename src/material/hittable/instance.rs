@@ -0,0 +1,61 @@
+use crate::material::Material;
+use crate::material::hittable::aabb::AABB;
+use crate::material::hittable::mesh::Mesh;
+use crate::material::hittable::transform::Transform;
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::matrix::Mat4;
+use crate::rtweekend::vec3::ray::Ray;
+use crate::rtweekend::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+// One placement of a shared mesh, it only adds a transform and optionally a material that
+// replaces the ones of the model file.
+pub(crate) struct Instance {
+    transform: Transform,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    pub(crate) fn new(
+        mesh: Arc<Mesh>,
+        matrix: Mat4,
+        material: Option<Arc<dyn Material>>,
+    ) -> Result<Instance, String> {
+        Ok(Instance {
+            transform: Transform::new(mesh, matrix)?,
+            material,
+        })
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
+        if !self.transform.hit(r, ray_t, rec) {
+            return false;
+        }
+        if let Some(material) = &self.material {
+            rec.mat = material.clone();
+        }
+        true
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.transform.bounding_box()
+    }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        match &self.material {
+            Some(material) => f(material),
+            None => self.transform.materials(f),
+        }
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.transform.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.transform.random(origin)
+    }
+}
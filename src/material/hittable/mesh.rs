@@ -0,0 +1,62 @@
+use crate::material::Material;
+use crate::material::hittable::aabb::AABB;
+use crate::material::hittable::triangle_mesh::TriangleMesh;
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::material::texture::model::load_obj;
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::vec3::ray::Ray;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+// The triangles of a model file in their own coordinates. A mesh is loaded once per file and
+// shared by every instance that places it in the scene.
+pub(crate) struct Mesh {
+    triangles: TriangleMesh,
+}
+
+// Meshes by the paths of their obj, mtl and end obj files.
+type MeshCache = HashMap<(String, String, Option<String>), Arc<Mesh>>;
+
+static MESHES: OnceLock<Mutex<MeshCache>> = OnceLock::new();
+
+impl Mesh {
+    // Loads `images/<obj_path>` with the materials of `images/<mtl_path>`, or hands out the
+    // mesh loaded from them before. With `end_obj_path` the mesh deforms into that file's
+    // vertices over the ray times, see load_obj.
+    pub(crate) fn load(
+        obj_path: &str,
+        mtl_path: &str,
+        end_obj_path: Option<&str>,
+    ) -> Result<Arc<Mesh>, String> {
+        let key = (
+            obj_path.to_string(),
+            mtl_path.to_string(),
+            end_obj_path.map(str::to_string),
+        );
+        let meshes = MESHES.get_or_init(Default::default);
+        if let Some(mesh) = meshes.lock().unwrap().get(&key) {
+            return Ok(mesh.clone());
+        }
+
+        // Parsed without holding the lock, so other files load meanwhile. If the same files were
+        // loaded twice at once, every caller gets the mesh stored first.
+        let mesh = Arc::new(Mesh {
+            triangles: load_obj(obj_path, mtl_path, end_obj_path, 1.0)?,
+        });
+        Ok(meshes.lock().unwrap().entry(key).or_insert(mesh).clone())
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
+        self.triangles.hit(r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.triangles.bounding_box()
+    }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.triangles.materials(f);
+    }
+}
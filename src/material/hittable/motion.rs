@@ -0,0 +1,452 @@
+use crate::material::Material;
+use crate::material::hittable::aabb::AABB;
+use crate::material::hittable::transform::{hit_transformed, transformed_box};
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::rtweekend::degrees_to_radians;
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::matrix::Mat4;
+use crate::rtweekend::vec3::ray::Ray;
+use crate::rtweekend::vec3::{Point3, Vec3, unit_vector};
+use std::sync::Arc;
+
+// Poses sampled between two keyframes to bound the motion.
+const BOUND_STEPS: usize = 16;
+
+// Pose of a moving object at one ray time. The object is scaled, then rotated, then translated.
+#[derive(Clone, Copy)]
+pub(crate) struct Keyframe {
+    pub time: f64,
+    pub scale: Vec3,
+    // Unit quaternion [w, x, y, z].
+    pub rotation: [f64; 4],
+    pub translate: Vec3,
+}
+
+impl Keyframe {
+    // Rotated counterclockwise by `angle` degrees about `axis`, as with Mat4::rotation.
+    pub(crate) fn new(time: f64, scale: Vec3, axis: Vec3, angle: f64, translate: Vec3) -> Keyframe {
+        let rotation = if angle == 0.0 {
+            [1.0, 0.0, 0.0, 0.0]
+        } else {
+            let a = unit_vector(&axis);
+            let (sin, cos) = (degrees_to_radians(angle) / 2.0).sin_cos();
+            [cos, a.x * sin, a.y * sin, a.z * sin]
+        };
+        Keyframe {
+            time,
+            scale,
+            rotation,
+            translate,
+        }
+    }
+
+    fn matrix(&self) -> Mat4 {
+        let [w, x, y, z] = self.rotation;
+        Mat4::translation(self.translate) * Mat4::quaternion(w, x, y, z) * Mat4::scaling(self.scale)
+    }
+
+    // Undoes the steps of `matrix` in reverse order. None when a scale factor is zero, which
+    // can happen where one passes through zero between keyframes.
+    fn inverse(&self) -> Option<Mat4> {
+        let s = self.scale;
+        if s.x == 0.0 || s.y == 0.0 || s.z == 0.0 {
+            return None;
+        }
+        let [w, x, y, z] = self.rotation;
+        Some(
+            Mat4::scaling(Vec3::new(1.0 / s.x, 1.0 / s.y, 1.0 / s.z))
+                * Mat4::quaternion(w, -x, -y, -z)
+                * Mat4::translation(-self.translate),
+        )
+    }
+
+    // The pose a share `t` of the way to `next`. Scale and translation change linearly, the
+    // rotation at a constant speed along the shorter way.
+    fn lerp(&self, next: &Keyframe, t: f64) -> Keyframe {
+        Keyframe {
+            time: self.time + t * (next.time - self.time),
+            scale: self.scale + t * (next.scale - self.scale),
+            rotation: slerp(self.rotation, next.rotation, t),
+            translate: self.translate + t * (next.translate - self.translate),
+        }
+    }
+}
+
+fn slerp(a: [f64; 4], b: [f64; 4], t: f64) -> [f64; 4] {
+    // q and -q are the same rotation, the one closer to a is the shorter way.
+    let mut cos = (0..4).map(|i| a[i] * b[i]).sum::<f64>();
+    let b = if cos < 0.0 {
+        cos = -cos;
+        b.map(|x| -x)
+    } else {
+        b
+    };
+
+    let (wa, wb) = if cos > 0.9995 {
+        // Nearly the same rotation, a straight line is as good and avoids dividing by ~0.
+        (1.0 - t, t)
+    } else {
+        let theta = cos.acos();
+        (
+            ((1.0 - t) * theta).sin() / theta.sin(),
+            (t * theta).sin() / theta.sin(),
+        )
+    };
+    let q: [f64; 4] = std::array::from_fn(|i| wa * a[i] + wb * b[i]);
+    let n = q.iter().map(|x| x * x).sum::<f64>().sqrt();
+    q.map(|x| x / n)
+}
+
+// Angle in radians of the rotation from a to b.
+fn rotation_angle(a: [f64; 4], b: [f64; 4]) -> f64 {
+    let cos = (0..4).map(|i| a[i] * b[i]).sum::<f64>().abs().min(1.0);
+    2.0 * cos.acos()
+}
+
+// An object moving along keyframed poses over the ray times, before the first keyframe it
+// holds the first pose and after the last one the last.
+pub(crate) struct MotionTransform {
+    object: Arc<dyn Hittable>,
+    keyframes: Vec<Keyframe>,
+    bbox: AABB,
+}
+
+impl MotionTransform {
+    pub(crate) fn new(
+        object: Arc<dyn Hittable>,
+        keyframes: Vec<Keyframe>,
+    ) -> Result<MotionTransform, String> {
+        if keyframes.is_empty() {
+            return Err("motion needs at least one keyframe".to_string());
+        }
+        if keyframes.windows(2).any(|k| k[0].time >= k[1].time) {
+            return Err("keyframe times must increase".to_string());
+        }
+        for k in &keyframes {
+            if k.scale.x == 0.0 || k.scale.y == 0.0 || k.scale.z == 0.0 {
+                return Err("keyframe scale factors must not be zero".to_string());
+            }
+            if k.rotation.iter().any(|x| !x.is_finite()) {
+                return Err("keyframe rotation axis must not be zero".to_string());
+            }
+        }
+
+        // Boxes of poses sampled along the motion. Between two samples the rotation carries the
+        // corners along arcs, which bulge out of their chords by at most the sagitta.
+        let object_box = object.bounding_box();
+        let mut radius: f64 = 0.0;
+        for x in [object_box.x.min, object_box.x.max] {
+            for y in [object_box.y.min, object_box.y.max] {
+                for z in [object_box.z.min, object_box.z.max] {
+                    radius = radius.max(Point3::new(x, y, z).length());
+                }
+            }
+        }
+        let mut bbox = transformed_box(&object_box, &keyframes[0].matrix());
+        for k in keyframes.windows(2) {
+            let max_scale = [k[0].scale, k[1].scale]
+                .iter()
+                .map(|s| s.x.abs().max(s.y.abs()).max(s.z.abs()))
+                .fold(0.0, f64::max);
+            let step_angle = rotation_angle(k[0].rotation, k[1].rotation) / BOUND_STEPS as f64;
+            let sagitta = radius * max_scale * (1.0 - (step_angle / 2.0).cos());
+            for step in 1..=BOUND_STEPS {
+                let pose = k[0].lerp(&k[1], step as f64 / BOUND_STEPS as f64);
+                let pose_box = transformed_box(&object_box, &pose.matrix());
+                let pose_box = AABB::new(
+                    pose_box.x.expand(2.0 * sagitta),
+                    pose_box.y.expand(2.0 * sagitta),
+                    pose_box.z.expand(2.0 * sagitta),
+                );
+                bbox = AABB::new_merge(&bbox, &pose_box);
+            }
+        }
+
+        Ok(MotionTransform {
+            object,
+            keyframes,
+            bbox,
+        })
+    }
+
+    fn pose(&self, time: f64) -> Keyframe {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keyframes[0];
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1];
+        }
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        a.lerp(b, (time - a.time) / (b.time - a.time))
+    }
+}
+
+impl Hittable for MotionTransform {
+    fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
+        let pose = self.pose(r.time);
+        let Some(inverse) = pose.inverse() else {
+            return false;
+        };
+        let matrix = pose.matrix();
+        hit_transformed(self.object.as_ref(), &matrix, &inverse, r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.object.materials(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::material::hittable::quad::Quad;
+    use crate::material::hittable::sphere::Sphere;
+    use crate::material::hittable::transform::Transform;
+    use crate::material::hittable::triangle_mesh::{Face, TriangleMesh};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!(
+            (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0),
+            "{} != {}",
+            a,
+            b
+        );
+    }
+
+    fn assert_close_vec(a: Vec3, b: Vec3) {
+        assert_close(a.x, b.x);
+        assert_close(a.y, b.y);
+        assert_close(a.z, b.z);
+    }
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::default())
+    }
+
+    fn hit(object: &dyn Hittable, r: &Ray) -> Option<HitRecord> {
+        let mut rec = HitRecord::new();
+        object
+            .hit(r, &mut Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    }
+
+    fn lerp(a: f64, b: f64, t: f64) -> f64 {
+        a + t * (b - a)
+    }
+
+    fn lerp_vec(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+        a + t * (b - a)
+    }
+
+    const AXIS: Vec3 = Vec3 {
+        x: 1.0,
+        y: 2.0,
+        z: -0.5,
+    };
+
+    // Three poses turning about one axis. From 120 to 460 degrees the shorter way is back by
+    // 20 degrees.
+    fn keyframes() -> Vec<Keyframe> {
+        vec![
+            Keyframe::new(
+                0.2,
+                Vec3::new(1.0, 0.5, 2.0),
+                AXIS,
+                0.0,
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+            Keyframe::new(
+                0.5,
+                Vec3::new(2.0, 1.0, 1.0),
+                AXIS,
+                120.0,
+                Vec3::new(3.0, -1.0, 2.0),
+            ),
+            Keyframe::new(
+                0.9,
+                Vec3::new(0.5, 0.5, 0.5),
+                AXIS,
+                460.0,
+                Vec3::new(-2.0, 0.0, 1.0),
+            ),
+        ]
+    }
+
+    // The pose at `time` worked out by hand from the keyframes above.
+    fn expected_matrix(time: f64) -> Mat4 {
+        let (scale, angle, translate) = if time <= 0.2 {
+            (Vec3::new(1.0, 0.5, 2.0), 0.0, Vec3::new(0.0, 1.0, 0.0))
+        } else if time <= 0.5 {
+            let t = (time - 0.2) / 0.3;
+            (
+                lerp_vec(Vec3::new(1.0, 0.5, 2.0), Vec3::new(2.0, 1.0, 1.0), t),
+                lerp(0.0, 120.0, t),
+                lerp_vec(Vec3::new(0.0, 1.0, 0.0), Vec3::new(3.0, -1.0, 2.0), t),
+            )
+        } else if time <= 0.9 {
+            let t = (time - 0.5) / 0.4;
+            (
+                lerp_vec(Vec3::new(2.0, 1.0, 1.0), Vec3::new(0.5, 0.5, 0.5), t),
+                lerp(120.0, 100.0, t),
+                lerp_vec(Vec3::new(3.0, -1.0, 2.0), Vec3::new(-2.0, 0.0, 1.0), t),
+            )
+        } else {
+            (Vec3::new(0.5, 0.5, 0.5), 100.0, Vec3::new(-2.0, 0.0, 1.0))
+        };
+        Mat4::IDENTITY
+            .scale(scale)
+            .rotate(AXIS, angle)
+            .translate(translate)
+    }
+
+    const TIMES: [f64; 8] = [0.0, 0.2, 0.31, 0.5, 0.64, 0.77, 0.9, 1.0];
+
+    #[test]
+    fn inverse_undoes_the_pose() {
+        for time in TIMES {
+            let pose = MotionTransform::new(
+                Arc::new(Sphere::new(Point3::default(), 1.0, material())),
+                keyframes(),
+            )
+            .unwrap()
+            .pose(time);
+            let (matrix, inverse) = (pose.matrix(), pose.inverse().unwrap());
+            let p = Point3::new(0.3, -2.0, 5.0);
+            assert_close_vec(inverse.transform_point(&matrix.transform_point(&p)), p);
+            assert_close_vec(matrix.transform_point(&inverse.transform_point(&p)), p);
+        }
+    }
+
+    #[test]
+    fn hits_at_a_time_match_a_static_transform_of_the_pose() {
+        let center = Point3::new(0.5, 0.0, -0.3);
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(center, 0.8, material()));
+        let moving = MotionTransform::new(sphere.clone(), keyframes()).unwrap();
+
+        for time in TIMES {
+            let matrix = expected_matrix(time);
+            let placed = Transform::new(sphere.clone(), matrix).unwrap();
+            let target = matrix.transform_point(&center);
+            for origin in [
+                Point3::new(10.0, 0.0, 0.0),
+                Point3::new(-3.0, 8.0, 4.0),
+                Point3::new(1.0, -2.0, -9.0),
+            ] {
+                let r = Ray::new_move(origin, target - origin, time);
+                let a = hit(&moving, &r).expect("the moving sphere is missed");
+                let b = hit(&placed, &r).expect("the placed sphere is missed");
+                assert_close(a.t, b.t);
+                assert_close_vec(a.p, b.p);
+                assert_close_vec(a.normal, b.normal);
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_contain_the_object_at_every_time() {
+        // Off the origin, so the turns carry it along wide arcs.
+        let object = Quad::new(
+            Point3::new(2.0, 1.0, -1.0),
+            Vec3::new(1.5, 0.0, 0.0),
+            Vec3::new(0.0, 0.5, 1.0),
+            material(),
+        );
+        let object_box = object.bounding_box();
+        let moving = MotionTransform::new(Arc::new(object), keyframes()).unwrap();
+        let bbox = moving.bounding_box();
+
+        for step in 0..=1000 {
+            let time = step as f64 / 1000.0;
+            let pose_box = transformed_box(&object_box, &moving.pose(time).matrix());
+            for (inner, outer) in [
+                (pose_box.x, bbox.x),
+                (pose_box.y, bbox.y),
+                (pose_box.z, bbox.z),
+            ] {
+                assert!(
+                    outer.min <= inner.min + 1e-9 && inner.max <= outer.max + 1e-9,
+                    "time {}: [{}, {}] is not inside [{}, {}]",
+                    time,
+                    inner.min,
+                    inner.max,
+                    outer.min,
+                    outer.max
+                );
+            }
+        }
+    }
+
+    fn mesh(positions: Vec<Point3>, normals: Vec<Vec3>) -> TriangleMesh {
+        TriangleMesh::new(
+            positions,
+            normals,
+            Vec::new(),
+            vec![face()],
+            vec![material()],
+        )
+    }
+
+    fn face() -> Face {
+        Face {
+            vertices: [0, 1, 2],
+            material: 0,
+            has_normals: true,
+            has_uvs: false,
+        }
+    }
+
+    #[test]
+    fn deforming_mesh_matches_its_start_and_end() {
+        let start = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, 2.0),
+        ];
+        let end = vec![
+            Point3::new(0.0, 1.0, 0.0),
+            Point3::new(2.0, 1.5, 0.0),
+            Point3::new(0.0, 0.5, 2.0),
+        ];
+        let start_normals = vec![Vec3::new(0.0, 1.0, 0.0); 3];
+        let end_normals = vec![
+            unit_vector(&Vec3::new(0.2, 1.0, 0.0)),
+            unit_vector(&Vec3::new(0.0, 1.0, 0.3)),
+            unit_vector(&Vec3::new(-0.1, 1.0, 0.1)),
+        ];
+        let deforming = TriangleMesh::new_move(
+            [start.clone(), end.clone()],
+            [start_normals.clone(), end_normals.clone()],
+            Vec::new(),
+            vec![face()],
+            vec![material()],
+        );
+        let middle: Vec<Point3> = (0..3).map(|i| lerp_vec(start[i], end[i], 0.5)).collect();
+        let middle_normals: Vec<Vec3> = (0..3)
+            .map(|i| lerp_vec(start_normals[i], end_normals[i], 0.5))
+            .collect();
+
+        for (time, still) in [
+            (0.0, mesh(start, start_normals)),
+            (0.5, mesh(middle, middle_normals)),
+            (1.0, mesh(end, end_normals)),
+        ] {
+            for target in [(0.5, 0.5), (1.2, 0.3), (0.1, 1.6)] {
+                let origin = Point3::new(target.0 + 0.3, 5.0, target.1 - 0.2);
+                let direction = Point3::new(target.0, 0.5, target.1) - origin;
+                let a = hit(&deforming, &Ray::new_move(origin, direction, time))
+                    .expect("the deforming mesh is missed");
+                let b = hit(&still, &Ray::new_move(origin, direction, time))
+                    .expect("the still mesh is missed");
+                assert_close(a.t, b.t);
+                assert_close_vec(a.p, b.p);
+                assert_close_vec(a.normal, b.normal);
+            }
+        }
+    }
+}
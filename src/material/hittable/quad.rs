@@ -4,6 +4,7 @@ use crate::material::hittable::hittable_list::HittableList;
 use crate::material::hittable::{HitRecord, Hittable};
 use crate::rtweekend::interval::Interval;
 use crate::rtweekend::random_double;
+use crate::rtweekend::sampler::{self, Dimension};
 use crate::rtweekend::vec3::ray::Ray;
 use crate::rtweekend::vec3::{Point3, Vec3, cross, dot, unit_vector};
 use std::f64::INFINITY;
@@ -107,6 +108,10 @@ impl Hittable for Quad {
         self.bbox
     }
 
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        f(&self.mat);
+    }
+
     fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
         let mut rec = HitRecord::new();
         if !self.hit(
@@ -124,7 +129,8 @@ impl Hittable for Quad {
     }
 
     fn random(&self, origin: &Vec3) -> Vec3 {
-        let p = self.q + (random_double() * self.u) + (random_double() * self.v);
+        let (a, b) = sampler::sample_2d(Dimension::Light);
+        let p = self.q + (a * self.u) + (b * self.v);
         p - *origin
     }
 }
@@ -3,9 +3,10 @@ use crate::material::hittable::aabb::AABB;
 use crate::material::hittable::{HitRecord, Hittable};
 use crate::material::onb::Onb;
 use crate::rtweekend::interval::Interval;
+use crate::rtweekend::sampler::{self, Dimension};
 use crate::rtweekend::vec3::ray::Ray;
 use crate::rtweekend::vec3::{Point3, Vec3};
-use crate::rtweekend::{PI, random_double, vec3};
+use crate::rtweekend::{PI, vec3};
 use std::f64::INFINITY;
 use std::sync::Arc;
 
@@ -58,8 +59,7 @@ impl Sphere {
     }
 
     fn random_to_sphere(&self, radius: f64, distance_squared: f64) -> Vec3 {
-        let r1 = random_double();
-        let r2 = random_double();
+        let (r1, r2) = sampler::sample_2d(Dimension::Light);
         let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
 
         let phi = 2.0 * PI * r1;
@@ -108,6 +108,10 @@ impl Hittable for Sphere {
         self.bbox.clone()
     }
 
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        f(&self.mat);
+    }
+
     fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
         //Only works for stationary spheres
 
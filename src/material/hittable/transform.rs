@@ -0,0 +1,318 @@
+use crate::material::Material;
+use crate::material::hittable::aabb::AABB;
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::matrix::Mat4;
+use crate::rtweekend::vec3::ray::Ray;
+use crate::rtweekend::vec3::{Point3, Vec3, unit_vector};
+use std::sync::Arc;
+
+// Places an object with an arbitrary affine matrix, e.g. rotated about any axis, scaled
+// unevenly or mirrored. Rays are taken into object space by the inverse, the hit is brought
+// back with the matrix and its normal with the inverse transpose.
+pub(crate) struct Transform {
+    object: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    bbox: AABB,
+}
+
+impl Transform {
+    pub(crate) fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Result<Transform, String> {
+        let inverse = matrix
+            .inverse()
+            .ok_or_else(|| "transform is not invertible".to_string())?;
+
+        Ok(Transform {
+            bbox: transformed_box(&object.bounding_box(), &matrix),
+            object,
+            matrix,
+            inverse,
+        })
+    }
+}
+
+// Box around the transformed corners of `bbox`.
+pub(crate) fn transformed_box(bbox: &AABB, matrix: &Mat4) -> AABB {
+    let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for x in [bbox.x.min, bbox.x.max] {
+        for y in [bbox.y.min, bbox.y.max] {
+            for z in [bbox.z.min, bbox.z.max] {
+                let p = matrix.transform_point(&Point3::new(x, y, z));
+                min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+                max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+            }
+        }
+    }
+    AABB::new_points(min, max)
+}
+
+// Intersects the object placed by `matrix`, whose inverse is `inverse`.
+pub(crate) fn hit_transformed(
+    object: &dyn Hittable,
+    matrix: &Mat4,
+    inverse: &Mat4,
+    r: &Ray,
+    ray_t: &mut Interval,
+    rec: &mut HitRecord,
+) -> bool {
+    // The direction isn't normalized again, so the ray parameter t stays the same in both
+    // spaces.
+    let local_r = Ray::new_move(
+        inverse.transform_point(&r.origin),
+        inverse.transform_vector(&r.direction),
+        r.time,
+    );
+
+    if !object.hit(&local_r, ray_t, rec) {
+        return false;
+    }
+
+    rec.p = matrix.transform_point(&rec.p);
+    rec.normal = unit_vector(&inverse.transform_normal(&rec.normal));
+    rec.tangent = unit_vector(&matrix.transform_vector(&rec.tangent));
+    rec.bitangent = unit_vector(&matrix.transform_vector(&rec.bitangent));
+
+    true
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
+        hit_transformed(
+            self.object.as_ref(),
+            &self.matrix,
+            &self.inverse,
+            r,
+            ray_t,
+            rec,
+        )
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.object.materials(f);
+    }
+
+    // Directions map to directions, but scaling stretches solid angles unevenly. For the
+    // world direction w and A the inverse of the 3x3 part, the object direction is A w / |A w|
+    // and the solid angle changes by |det A| / |A w|^3 (w of unit length).
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let direction = unit_vector(direction);
+        let local_direction = self.inverse.transform_vector(&direction);
+        let pdf = self
+            .object
+            .pdf_value(&self.inverse.transform_point(origin), &local_direction);
+        pdf * self.inverse.determinant3().abs() / local_direction.length().powi(3)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let local_origin = self.inverse.transform_point(origin);
+        self.matrix
+            .transform_vector(&self.object.random(&local_origin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::hittable::quad::Quad;
+    use crate::material::hittable::sphere::Sphere;
+    use crate::material::hittable::triangle_mesh::{Face, TriangleMesh};
+    use crate::material::hittable::{RotateY, Translate};
+    use crate::material::texture::UV;
+    use crate::material::{DiffuseLight, Material};
+    use crate::rtweekend::color::Color;
+    use crate::rtweekend::vec3::dot;
+
+    fn light() -> Arc<dyn Material> {
+        Arc::new(DiffuseLight::new_color(&Color::new(1.0, 1.0, 1.0)))
+    }
+
+    fn origins() -> [Point3; 4] {
+        [
+            Point3::new(0.0, -3.0, 0.5),
+            Point3::new(4.0, -1.0, 2.0),
+            Point3::new(-3.0, 5.0, -4.0),
+            Point3::new(0.5, 8.0, 1.0),
+        ]
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!(
+            (a - b).abs() <= 1e-9 * a.abs().max(b.abs()).max(1.0),
+            "{} != {}",
+            a,
+            b
+        );
+    }
+
+    fn assert_close_vec(a: Vec3, b: Vec3) {
+        assert_close(a.x, b.x);
+        assert_close(a.y, b.y);
+        assert_close(a.z, b.z);
+    }
+
+    // A transformed light and the same light placed directly where the transform puts it give
+    // the same densities, and the directions sampled from the first all reach the second.
+    fn assert_same_light(transformed: &dyn Hittable, placed: &dyn Hittable, targets: &[Point3]) {
+        for origin in origins() {
+            for target in targets {
+                let direction = *target - origin;
+                let pdf = placed.pdf_value(&origin, &direction);
+                assert!(pdf > 0.0);
+                assert_close(transformed.pdf_value(&origin, &direction), pdf);
+            }
+            let away = origin - targets[0];
+            assert_eq!(transformed.pdf_value(&origin, &away), 0.0);
+
+            for _ in 0..200 {
+                let direction = transformed.random(&origin);
+                let mut rec = HitRecord::new();
+                let r = Ray::new(origin, direction);
+                assert!(transformed.hit(&r, &mut Interval::new(0.001, f64::INFINITY), &mut rec));
+                assert!(placed.pdf_value(&origin, &direction) > 0.0);
+            }
+        }
+    }
+
+    fn quad_targets(q: Point3, u: Vec3, v: Vec3) -> Vec<Point3> {
+        [(0.5, 0.5), (0.1, 0.8), (0.9, 0.2), (0.3, 0.05)]
+            .iter()
+            .map(|&(a, b)| q + a * u + b * v)
+            .collect()
+    }
+
+    #[test]
+    fn transformed_quad_light_matches_placed_quad() {
+        // Uneven scaling, so the solid angle term of pdf_value is exercised.
+        let matrix = Mat4::IDENTITY
+            .scale(Vec3::new(2.0, 1.0, 0.5))
+            .rotate(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            .translate(Vec3::new(1.0, 2.0, -1.0));
+        let q = Point3::new(-0.5, 0.0, -0.5);
+        let (u, v) = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let transformed = Transform::new(Arc::new(Quad::new(q, u, v, light())), matrix).unwrap();
+        let (q, u, v) = (
+            matrix.transform_point(&q),
+            matrix.transform_vector(&u),
+            matrix.transform_vector(&v),
+        );
+        let placed = Quad::new(q, u, v, light());
+
+        assert_same_light(&transformed, &placed, &quad_targets(q, u, v));
+    }
+
+    #[test]
+    fn transformed_sphere_light_matches_placed_sphere() {
+        let matrix = Mat4::IDENTITY
+            .scale(Vec3::new(1.5, 1.5, 1.5))
+            .rotate(Vec3::new(0.0, 1.0, 1.0), -50.0)
+            .translate(Vec3::new(-1.0, 2.0, 0.5));
+        let center = Point3::new(0.5, 0.0, -0.5);
+        let transformed =
+            Transform::new(Arc::new(Sphere::new(center, 0.5, light())), matrix).unwrap();
+        let center = matrix.transform_point(&center);
+        let placed = Sphere::new(center, 0.75, light());
+
+        let targets = [
+            center,
+            center + Vec3::new(0.3, 0.2, 0.0),
+            center + Vec3::new(0.0, -0.4, 0.3),
+        ];
+        assert_same_light(&transformed, &placed, &targets);
+    }
+
+    #[test]
+    fn translated_rotated_quad_light_matches_placed_quad() {
+        let q = Point3::new(-0.5, 0.0, -0.5);
+        let (u, v) = (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+        let offset = Vec3::new(2.0, 1.0, -3.0);
+        let wrapped = Translate::new(
+            Arc::new(RotateY::new(Arc::new(Quad::new(q, u, v, light())), 40.0)),
+            offset,
+        );
+        let matrix = Mat4::IDENTITY.rotate_y(40.0).translate(offset);
+        let (q, u, v) = (
+            matrix.transform_point(&q),
+            matrix.transform_vector(&u),
+            matrix.transform_vector(&v),
+        );
+        let placed = Quad::new(q, u, v, light());
+
+        assert_same_light(&wrapped, &placed, &quad_targets(q, u, v));
+    }
+
+    // One triangle with texture coordinates, so hits on it carry a tangent frame.
+    fn triangle(points: [Point3; 3]) -> TriangleMesh {
+        TriangleMesh::new(
+            points.to_vec(),
+            Vec::new(),
+            vec![UV::new(0.0, 0.0), UV::new(1.0, 0.0), UV::new(0.0, 1.0)],
+            vec![Face {
+                vertices: [0, 1, 2],
+                material: 0,
+                has_normals: false,
+                has_uvs: true,
+            }],
+            vec![light()],
+        )
+    }
+
+    fn hit_frame(object: &dyn Hittable, r: &Ray) -> HitRecord {
+        let mut rec = HitRecord::new();
+        assert!(object.hit(r, &mut Interval::new(0.001, f64::INFINITY), &mut rec));
+        rec
+    }
+
+    #[test]
+    fn tangent_frame_turns_with_the_normal() {
+        let points = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, 1.0),
+        ];
+        let rotation = Mat4::IDENTITY
+            .rotate(Vec3::new(1.0, 2.0, 3.0), 70.0)
+            .translate(Vec3::new(0.5, -1.0, 2.0));
+        let scaled = Mat4::IDENTITY
+            .scale(Vec3::new(3.0, 1.0, 0.5))
+            .rotate(Vec3::new(1.0, 0.0, 1.0), 25.0);
+
+        for matrix in [rotation, scaled] {
+            let transformed = Transform::new(Arc::new(triangle(points)), matrix).unwrap();
+            let placed = triangle(points.map(|p| matrix.transform_point(&p)));
+            let target = matrix.transform_point(&Point3::new(0.25, 0.0, 0.25));
+            let origin = target + 2.0 * matrix.transform_normal(&Vec3::new(0.2, 1.0, 0.1));
+            let r = Ray::new(origin, target - origin);
+
+            let a = hit_frame(&transformed, &r);
+            let b = hit_frame(&placed, &r);
+            assert_close_vec(a.p, b.p);
+            assert_close_vec(a.normal, b.normal);
+            assert_close_vec(a.tangent, b.tangent);
+            assert_close(dot(&a.tangent, &a.normal), 0.0);
+            assert_close(dot(&a.bitangent, &a.normal), 0.0);
+            if matrix == rotation {
+                assert_close_vec(a.bitangent, b.bitangent);
+            }
+        }
+
+        // The y rotation wrapper turns the frame as well.
+        let rotated = RotateY::new(Arc::new(triangle(points)), 60.0);
+        let matrix = Mat4::IDENTITY.rotate_y(60.0);
+        let placed = triangle(points.map(|p| matrix.transform_point(&p)));
+        let target = matrix.transform_point(&Point3::new(0.3, 0.0, 0.2));
+        let r = Ray::new(
+            target + Vec3::new(0.1, 2.0, 0.0),
+            Vec3::new(-0.1, -2.0, 0.0),
+        );
+        let (a, b) = (hit_frame(&rotated, &r), hit_frame(&placed, &r));
+        assert_close_vec(a.normal, b.normal);
+        assert_close_vec(a.tangent, b.tangent);
+        assert_close_vec(a.bitangent, b.bitangent);
+    }
+}
@@ -4,6 +4,7 @@ use crate::material::hittable::{HitRecord, Hittable};
 use crate::material::texture::UV;
 use crate::rtweekend::interval::Interval;
 use crate::rtweekend::random_double;
+use crate::rtweekend::sampler::{self, Dimension};
 use crate::rtweekend::vec3::ray::Ray;
 use crate::rtweekend::vec3::{Point3, Vec3, cross, dot, unit_vector};
 use std::f64::INFINITY;
@@ -189,6 +190,10 @@ impl Hittable for Triangle {
         self.bbox
     }
 
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        f(&self.mat);
+    }
+
     fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
         let mut rec = HitRecord::new();
         if !self.hit(
@@ -206,8 +211,7 @@ impl Hittable for Triangle {
     }
 
     fn random(&self, origin: &Vec3) -> Vec3 {
-        let mut vx = random_double();
-        let mut vy = random_double();
+        let (mut vx, mut vy) = sampler::sample_2d(Dimension::Light);
         if vx + vy > 1.0 {
             vx = 1.0 - vx;
             vy = 1.0 - vy;
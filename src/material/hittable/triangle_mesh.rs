@@ -0,0 +1,236 @@
+use crate::material::Material;
+use crate::material::hittable::aabb::AABB;
+use crate::material::hittable::bvh::build;
+use crate::material::hittable::bvh::flat::FlatBvh;
+use crate::material::hittable::{HitRecord, Hittable};
+use crate::material::texture::UV;
+use crate::rtweekend::interval::Interval;
+use crate::rtweekend::random_double;
+use crate::rtweekend::vec3::ray::Ray;
+use crate::rtweekend::vec3::{Point3, Vec3, cross, dot, unit_vector};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+// Triangles sharing vertex buffers. Normals and texture coordinates are per vertex, but not
+// every face has them: faces without normals are shaded flat, and faces without texture
+// coordinates use their barycentric coordinates, as quads do.
+pub(crate) struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<UV>,
+    faces: Vec<Face>,
+    materials: Vec<Arc<dyn Material>>,
+    // Where the vertices of a deforming mesh are at ray time 1, empty for a still one. They move
+    // linearly from their positions at time 0.
+    end_positions: Vec<Point3>,
+    end_normals: Vec<Vec3>,
+    // Over the faces, which are stored in the order of its leaves.
+    bvh: FlatBvh,
+}
+
+// Three vertex indices, the index of the material and whether the normal and texture
+// coordinate buffers hold values for the vertices of this face.
+#[derive(Clone, Copy)]
+pub(crate) struct Face {
+    pub vertices: [u32; 3],
+    pub material: u32,
+    pub has_normals: bool,
+    pub has_uvs: bool,
+}
+
+impl TriangleMesh {
+    pub(crate) fn new(
+        positions: Vec<Point3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<UV>,
+        faces: Vec<Face>,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> TriangleMesh {
+        TriangleMesh::new_move(
+            [positions, Vec::new()],
+            [normals, Vec::new()],
+            uvs,
+            faces,
+            materials,
+        )
+    }
+
+    // A mesh deforming between two sets of vertices with the same faces, at ray times 0 and 1.
+    // The second sets may be empty for a mesh that doesn't move, or have no normals.
+    pub(crate) fn new_move(
+        [positions, end_positions]: [Vec<Point3>; 2],
+        [normals, end_normals]: [Vec<Vec3>; 2],
+        uvs: Vec<UV>,
+        faces: Vec<Face>,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> TriangleMesh {
+        // A linearly moving triangle stays within the box of its start and end.
+        let triangle_box = |vertices: &[Point3], face: &Face| {
+            let [a, b, c] = face.vertices.map(|i| vertices[i as usize]);
+            AABB::new_merge(&AABB::new_points(a, b), &AABB::new_points(a, c))
+        };
+        let boxes: Vec<AABB> = faces
+            .par_iter()
+            .map(|face| {
+                let bbox = triangle_box(&positions, face);
+                if end_positions.is_empty() {
+                    bbox
+                } else {
+                    AABB::new_merge(&bbox, &triangle_box(&end_positions, face))
+                }
+            })
+            .collect();
+        let (root, order) = build::build(&boxes);
+        TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            faces: order.iter().map(|&face| faces[face]).collect(),
+            materials,
+            end_positions,
+            end_normals,
+            bvh: FlatBvh::new(root),
+        }
+    }
+
+    fn vertex(&self, i: usize, time: f64) -> Point3 {
+        if self.end_positions.is_empty() {
+            self.positions[i]
+        } else {
+            self.positions[i] + time * (self.end_positions[i] - self.positions[i])
+        }
+    }
+
+    fn vertex_normal(&self, i: usize, time: f64) -> Vec3 {
+        if self.end_normals.is_empty() {
+            self.normals[i]
+        } else {
+            self.normals[i] + time * (self.end_normals[i] - self.normals[i])
+        }
+    }
+
+    // Möller-Trumbore, returns t and the barycentric coordinates of the second and third
+    // vertex.
+    fn intersect(&self, face: usize, r: &Ray, ray_t: &Interval) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.faces[face]
+            .vertices
+            .map(|i| self.vertex(i as usize, r.time));
+        let e1 = b - a;
+        let e2 = c - a;
+        let p = cross(&r.direction, &e2);
+        let det = dot(&e1, &p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = r.origin - a;
+        let u = dot(&s, &p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = cross(&s, &e1);
+        let v = dot(&r.direction, &q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = dot(&e2, &q) * inv_det;
+        ray_t.surrounds(t).then_some((t, u, v))
+    }
+
+    // Texture coordinates of the vertices of a face, (0, 0), (1, 0) and (0, 1) for faces
+    // without them, which makes the coordinates of a hit its barycentric ones.
+    fn face_uvs(&self, face: &Face) -> [UV; 3] {
+        if face.has_uvs {
+            face.vertices.map(|i| self.uvs[i as usize].clone())
+        } else {
+            [UV::new(0.0, 0.0), UV::new(1.0, 0.0), UV::new(0.0, 1.0)]
+        }
+    }
+
+    fn fill_record(&self, face: usize, r: &Ray, t: f64, b1: f64, b2: f64, rec: &mut HitRecord) {
+        let face = &self.faces[face];
+        let [i0, i1, i2] = face.vertices.map(|i| i as usize);
+        let [p0, p1, p2] = [i0, i1, i2].map(|i| self.vertex(i, r.time));
+        let b0 = 1.0 - b1 - b2;
+
+        let geometric = unit_vector(&cross(&(p1 - p0), &(p2 - p0)));
+        let mut normal = if face.has_normals {
+            let [n0, n1, n2] = [i0, i1, i2].map(|i| self.vertex_normal(i, r.time));
+            unit_vector(&(b0 * n0 + b1 * n1 + b2 * n2))
+        } else {
+            geometric
+        };
+        if !normal.x.is_finite() {
+            normal = geometric;
+        }
+
+        let [uv0, uv1, uv2] = self.face_uvs(face);
+        let uv = uv0.clone() * b0 + uv1.clone() * b1 + uv2.clone() * b2;
+
+        // Tangent along increasing u, from the texture coordinate deltas of the edges.
+        let (du1, dv1) = (uv1.u - uv0.u, uv1.v - uv0.v);
+        let (du2, dv2) = (uv2.u - uv0.u, uv2.v - uv0.v);
+        let uv_det = du1 * dv2 - du2 * dv1;
+        let tangent = if uv_det.abs() > 1e-12 {
+            ((p1 - p0) * dv2 - (p2 - p0) * dv1) / uv_det
+        } else {
+            p1 - p0
+        };
+        let tangent = unit_vector(&(tangent - normal * dot(&tangent, &normal)));
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = uv.u;
+        rec.v = uv.v;
+        rec.mat = self.materials[face.material as usize].clone();
+        // Two sided like the other shapes, see set_face_normal: the normal faces the ray, so
+        // materials scatter and refract on the side that was hit.
+        rec.front_face = dot(&r.direction, &normal) < 0.0;
+        rec.normal = if rec.front_face { normal } else { -normal };
+        rec.tangent = tangent;
+        rec.bitangent = cross(&rec.normal, &tangent);
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: &mut Interval, rec: &mut HitRecord) -> bool {
+        let mut found = None;
+        self.bvh.traverse(r, *ray_t, |leaf, closest| {
+            let mut hit_anything = false;
+            for face in leaf {
+                if let Some((t, b1, b2)) = self.intersect(face, r, closest) {
+                    let material = &self.materials[self.faces[face].material as usize];
+                    // Only alpha mapped faces let rays pass, with the chance found at the texture
+                    // coordinates of the hit.
+                    if material.check_alpha_mapping() {
+                        let [uv0, uv1, uv2] = self.face_uvs(&self.faces[face]);
+                        let uv = uv0 * (1.0 - b1 - b2) + uv1 * b1 + uv2 * b2;
+                        if random_double() < material.get_alpha_mapping(uv.u, uv.v) {
+                            continue;
+                        }
+                    }
+                    closest.max = t;
+                    found = Some((face, t, b1, b2));
+                    hit_anything = true;
+                }
+            }
+            hit_anything
+        });
+
+        match found {
+            Some((face, t, b1, b2)) => {
+                self.fill_record(face, r, t, b1, b2, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bvh.bounding_box()
+    }
+
+    fn materials(&self, f: &mut dyn FnMut(&Arc<dyn Material>)) {
+        self.materials.iter().for_each(f);
+    }
+}
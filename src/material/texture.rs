@@ -136,6 +136,10 @@ impl ImageTexture {
             image: RtwImage::new(filename),
         }
     }
+
+    pub fn new_image(image: RtwImage) -> Self {
+        Self { image }
+    }
 }
 
 impl Texture for ImageTexture {
@@ -202,12 +206,19 @@ struct MappedTexture {
 }
 
 impl MappedTexture {
-    pub fn new(path: &str, normal_path: Option<&str>, alpha_path: Option<&str>) -> Self {
-        Self {
-            color_map: RtwImage::new(path),
-            normal_map: normal_path.map(RtwImage::new),
-            alpha_map: alpha_path.map(RtwImage::new),
-        }
+    pub fn open(
+        path: &str,
+        normal_path: Option<&str>,
+        alpha_path: Option<&str>,
+    ) -> Result<Self, String> {
+        let open = |path: &str| {
+            RtwImage::open(path).ok_or_else(|| format!("cannot load image \"{}\"", path))
+        };
+        Ok(Self {
+            color_map: open(path)?,
+            normal_map: normal_path.map(open).transpose()?,
+            alpha_map: alpha_path.map(open).transpose()?,
+        })
     }
 }
 
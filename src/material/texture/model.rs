@@ -1,152 +1,180 @@
-use crate::material::Lambertian;
-use crate::material::hittable::bvh::BvhNode;
 use crate::material::hittable::hittable_list::HittableList;
-use crate::material::hittable::triangle::Triangle;
-use crate::material::hittable::{RotateY, Translate};
+use crate::material::hittable::instance::Instance;
+use crate::material::hittable::mesh::Mesh;
+use crate::material::hittable::triangle_mesh::{Face, TriangleMesh};
 use crate::material::texture::UV;
 use crate::material::texture::mtl::{create_texture, process_mtl_file};
+use crate::material::{Lambertian, Material};
 use crate::rtweekend::color::Color;
+use crate::rtweekend::matrix::Mat4;
 use crate::rtweekend::vec3::{Point3, Vec3};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tobj::LoadOptions;
 
-pub fn load_obj(obj_path: &str, mtl_path: &str, scale: f64) -> Vec<Triangle> {
+// Every model and material file read so far, see model_files.
+static MODEL_FILES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// The files under `images/` that models were loaded from, so a checkpoint can tell when one of
+// them changed.
+pub(crate) fn model_files() -> Vec<String> {
+    MODEL_FILES.lock().unwrap().clone()
+}
+
+fn read_obj(obj_path: &str) -> Result<(Vec<tobj::Model>, Vec<tobj::Material>), String> {
+    MODEL_FILES
+        .lock()
+        .unwrap()
+        .push(format!("images/{}", obj_path));
     let (models, materials) = tobj::load_obj(
-        &format!("images/{}", obj_path),
+        format!("images/{}", obj_path),
         &LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
     )
-    .expect("Failed to load obj");
-    let materials = materials.unwrap();
+    .map_err(|err| format!("failed to load obj \"images/{}\": {}", obj_path, err))?;
+    Ok((models, materials.unwrap_or_default()))
+}
 
-    let mut res_map = HashMap::new();
+// Reads a model file into one mesh, every object of the file keeps its own material. With
+// `end_obj_path` the mesh deforms into the vertices of that file over the ray times, which must
+// be the same model with its vertices moved.
+pub(crate) fn load_obj(
+    obj_path: &str,
+    mtl_path: &str,
+    end_obj_path: Option<&str>,
+    scale: f64,
+) -> Result<TriangleMesh, String> {
+    let (models, materials) = read_obj(obj_path)?;
+    MODEL_FILES
+        .lock()
+        .unwrap()
+        .push(format!("images/{}", mtl_path));
 
-    if let Ok(parsed) =
-        std::panic::catch_unwind(|| process_mtl_file(&format!("images/{}", mtl_path)))
-    {
-        for (_, info) in parsed {
-            let tex = create_texture(&info);
-            let mat = Arc::new(Lambertian::new_tex(tex));
-            res_map.insert(info.name.clone(), mat);
-        }
+    let mut res_map = HashMap::new();
+    for (_, info) in process_mtl_file(&format!("images/{}", mtl_path))? {
+        let tex = create_texture(&info).map_err(|err| {
+            format!(
+                "material \"{}\" of \"images/{}\": {}",
+                info.name, mtl_path, err
+            )
+        })?;
+        let mat = Arc::new(Lambertian::new_tex(tex));
+        res_map.insert(info.name.clone(), mat);
     }
 
-    let mut triangles = Vec::new();
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut faces = Vec::new();
+    let mut mesh_materials: Vec<Arc<dyn Material>> = Vec::new();
+    let mut material_index = HashMap::new();
+    let (mut has_normals, mut has_uvs) = (false, false);
 
     for model in &models {
         let mesh = &model.mesh;
-        let positions = &mesh.positions;
-        let normals = &mesh.normals;
-        let texcoords = &mesh.texcoords;
-        let indices = &mesh.indices;
+        let first = positions.len() as u32;
 
-        let material = if let Some(mat_id) = mesh.material_id {
-            let obj_material = materials.get(mat_id);
-            if let Some(mat) = obj_material {
-                if let Some(name) = res_map.get(&mat.name) {
-                    name.clone()
+        let material = *material_index.entry(mesh.material_id).or_insert_with(|| {
+            let material: Arc<dyn Material> = if let Some(mat_id) = mesh.material_id {
+                let obj_material = materials.get(mat_id);
+                if let Some(mat) = obj_material {
+                    if let Some(name) = res_map.get(&mat.name) {
+                        name.clone()
+                    } else {
+                        Arc::new(Lambertian::new(&Color::new(0.8, 0.0, 0.0)))
+                    }
                 } else {
-                    Arc::new(Lambertian::new(&Color::new(0.8, 0.0, 0.0)))
+                    Arc::new(Lambertian::new(&Color::new(0.0, 0.8, 0.0)))
                 }
             } else {
-                Arc::new(Lambertian::new(&Color::new(0.0, 0.8, 0.0)))
-            }
-        } else {
-            Arc::new(Lambertian::new(&Color::new(0.0, 0.0, 0.8)))
-        };
-
-        for i in (0..indices.len()).step_by(3) {
-            let v0 = Point3::new(
-                positions[3 * indices[i] as usize] as f64,
-                positions[3 * indices[i] as usize + 1] as f64,
-                positions[3 * indices[i] as usize + 2] as f64,
-            ) * scale;
-            let v1 = Point3::new(
-                positions[3 * indices[i + 1] as usize] as f64,
-                positions[3 * indices[i + 1] as usize + 1] as f64,
-                positions[3 * indices[i + 1] as usize + 2] as f64,
-            ) * scale;
-            let v2 = Point3::new(
-                positions[3 * indices[i + 2] as usize] as f64,
-                positions[3 * indices[i + 2] as usize + 1] as f64,
-                positions[3 * indices[i + 2] as usize + 2] as f64,
-            ) * scale;
-
-            let uv0 = if 2 * (indices[i] + 1) <= texcoords.len() as u32 {
-                UV::new(
-                    texcoords[2 * indices[i] as usize] as f64,
-                    texcoords[2 * indices[i] as usize + 1] as f64,
-                )
-            } else {
-                UV::default()
-            };
-            let uv1 = if 2 * (indices[i + 1] + 1) <= texcoords.len() as u32 {
-                UV::new(
-                    texcoords[2 * indices[i + 1] as usize] as f64,
-                    texcoords[2 * indices[i + 1] as usize + 1] as f64,
-                )
-            } else {
-                UV::default()
-            };
-            let uv2 = if 2 * (indices[i + 2] + 1) <= texcoords.len() as u32 {
-                UV::new(
-                    texcoords[2 * indices[i + 2] as usize] as f64,
-                    texcoords[2 * indices[i + 2] as usize + 1] as f64,
-                )
-            } else {
-                UV::default()
+                Arc::new(Lambertian::new(&Color::new(0.0, 0.0, 0.8)))
             };
+            mesh_materials.push(material);
+            mesh_materials.len() as u32 - 1
+        });
 
-            let n0 = if 3 * (indices[i] + 1) <= normals.len() as u32 {
-                Point3::new(
-                    normals[3 * indices[i] as usize] as f64,
-                    normals[3 * indices[i] as usize + 1] as f64,
-                    normals[3 * indices[i] as usize + 2] as f64,
-                )
-            } else {
-                Point3::default()
-            };
-            let n1 = if 3 * (indices[i + 1] + 1) <= normals.len() as u32 {
-                Point3::new(
-                    normals[3 * indices[i + 1] as usize] as f64,
-                    normals[3 * indices[i + 1] as usize + 1] as f64,
-                    normals[3 * indices[i + 1] as usize + 2] as f64,
-                )
-            } else {
-                Point3::default()
-            };
-            let n2 = if 3 * (indices[i] + 1) <= normals.len() as u32 {
-                Point3::new(
-                    normals[3 * indices[i + 2] as usize] as f64,
-                    normals[3 * indices[i + 2] as usize + 1] as f64,
-                    normals[3 * indices[i + 2] as usize + 2] as f64,
-                )
-            } else {
-                Point3::default()
-            };
+        // With a single index the buffers line up. Objects without normals or texture
+        // coordinates get zeros to keep them aligned, their faces are marked as having none.
+        for (k, p) in mesh.positions.chunks_exact(3).enumerate() {
+            positions.push(Point3::new(p[0] as f64, p[1] as f64, p[2] as f64) * scale);
+            normals.push(match mesh.normals.get(3 * k..3 * k + 3) {
+                Some(n) => Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64),
+                None => Vec3::default(),
+            });
+            uvs.push(match mesh.texcoords.get(2 * k..2 * k + 2) {
+                Some(t) => UV::new(t[0] as f64, t[1] as f64),
+                None => UV::default(),
+            });
+        }
+        let (object_normals, object_uvs) = (!mesh.normals.is_empty(), !mesh.texcoords.is_empty());
+        has_normals |= object_normals;
+        has_uvs |= object_uvs;
 
-            triangles.push(Triangle::new_point(
-                v0,
-                v1,
-                v2,
-                uv0,
-                uv1,
-                uv2,
-                n0,
-                n1,
-                n2,
-                material.clone(),
-            ));
+        for face in mesh.indices.chunks_exact(3) {
+            faces.push(Face {
+                vertices: [first + face[0], first + face[1], first + face[2]],
+                material,
+                has_normals: object_normals,
+                has_uvs: object_uvs,
+            });
         }
     }
 
-    triangles
+    if !has_normals {
+        normals.clear();
+    }
+    if !has_uvs {
+        uvs.clear();
+    }
+
+    let Some(end_obj_path) = end_obj_path else {
+        return Ok(TriangleMesh::new(
+            positions,
+            normals,
+            uvs,
+            faces,
+            mesh_materials,
+        ));
+    };
+    let (end_models, _) = read_obj(end_obj_path)?;
+    let mut end_positions = Vec::with_capacity(positions.len());
+    let mut end_normals = Vec::with_capacity(normals.len());
+    for model in &end_models {
+        let mesh = &model.mesh;
+        // Vertices without an end normal keep their start normal, as the buffers must line up.
+        for (k, p) in mesh.positions.chunks_exact(3).enumerate() {
+            end_positions.push(Point3::new(p[0] as f64, p[1] as f64, p[2] as f64) * scale);
+            if has_normals {
+                let start = normals.get(end_normals.len()).copied().unwrap_or_default();
+                end_normals.push(match mesh.normals.get(3 * k..3 * k + 3) {
+                    Some(n) => Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64),
+                    None => start,
+                });
+            }
+        }
+    }
+    if end_positions.len() != positions.len() {
+        return Err(format!(
+            "\"images/{}\" has {} vertices, \"images/{}\" has {}",
+            end_obj_path,
+            end_positions.len(),
+            obj_path,
+            positions.len()
+        ));
+    }
+    Ok(TriangleMesh::new_move(
+        [positions, end_positions],
+        [normals, end_normals],
+        uvs,
+        faces,
+        mesh_materials,
+    ))
 }
 
+// Places a model file in the world. Calling this again for the same file reuses its mesh.
 pub fn load_model(
     obj_path: &str,
     mtl_path: &str,
@@ -154,14 +182,12 @@ pub fn load_model(
     ang: f64,
     place: Vec3,
     scale: f64,
-) {
-    let triangles = load_obj(obj_path, mtl_path, scale);
-    let mut model = HittableList::new();
-    for triangle in triangles {
-        model.add(Arc::new(triangle));
-    }
-    let bvh_model = BvhNode::new(model);
-    let model = Arc::new(RotateY::new(Arc::new(bvh_model), ang));
-    let model = Arc::new(Translate::new(model, place));
-    world.add(model);
+) -> Result<(), String> {
+    let mesh = Mesh::load(obj_path, mtl_path, None)?;
+    let matrix = Mat4::IDENTITY
+        .scale(Vec3::new(scale, scale, scale))
+        .rotate_y(ang)
+        .translate(place);
+    world.add(Arc::new(Instance::new(mesh, matrix, None)?));
+    Ok(())
 }
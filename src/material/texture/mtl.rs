@@ -18,8 +18,9 @@ pub struct MtlInfo {
     pub map_d: Option<String>,
 }
 
-pub fn process_mtl_file(path: &str) -> HashMap<String, MtlInfo> {
-    let file = File::open(path).expect("Cannot open mtl file");
+pub fn process_mtl_file(path: &str) -> Result<HashMap<String, MtlInfo>, String> {
+    let file =
+        File::open(path).map_err(|err| format!("failed to load mtl \"{}\": {}", path, err))?;
     let reader = BufReader::new(file);
 
     let mut res = HashMap::new();
@@ -78,25 +79,22 @@ pub fn process_mtl_file(path: &str) -> HashMap<String, MtlInfo> {
         res.insert(now.name.clone(), now);
     }
 
-    res
+    Ok(res)
 }
 
-pub fn create_texture(material: &MtlInfo) -> Arc<dyn Texture + Send + Sync> {
+pub fn create_texture(material: &MtlInfo) -> Result<Arc<dyn Texture + Send + Sync>, String> {
     // 优先检查是否有漫反射贴图 (map_Kd)
     if let Some(map_kd_filename) = &material.map_kd {
-        Arc::new(MappedTexture::new(
-            material
-                .map_kd
-                .as_deref()
-                .unwrap_or("textures/diffuse_default.jpg"),
+        Ok(Arc::new(MappedTexture::open(
+            map_kd_filename,
             material.map_bump.as_deref(),
             material.map_d.as_deref(),
-        ))
+        )?))
     } else {
-        Arc::new(SolidColor::new(&Color::new(
+        Ok(Arc::new(SolidColor::new(&Color::new(
             material.kd.x,
             material.kd.y,
             material.kd.z,
-        )))
+        ))))
     }
 }
@@ -14,39 +14,36 @@ pub struct RtwImage {
 
 impl RtwImage {
     pub fn new(image_filename: &str) -> Self {
+        Self::open(image_filename)
+            .unwrap_or_else(|| panic!("ERROR: Could not load image file \"{}\".", image_filename))
+    }
+
+    pub fn open(image_filename: &str) -> Option<Self> {
         let filename = image_filename;
         let imagedir = env::var("RTW_IMAGES").unwrap_or_else(|_| String::from("images"));
 
         let mut now = Self::default();
         if !imagedir.is_empty() && now.load(&format!("{}/{}", imagedir, filename)) {
-            return now;
+            return Some(now);
         }
         if now.load(filename) {
-            return now;
-        }
-        if now.load(&format!("images/{}", filename)) {
-            return now;
-        }
-        if now.load(&format!("../images/{}", filename)) {
-            return now;
-        }
-        if now.load(&format!("../../images/{}", filename)) {
-            return now;
+            return Some(now);
         }
-        if now.load(&format!("../../../images/{}", filename)) {
-            return now;
-        }
-        if now.load(&format!("../../../../images/{}", filename)) {
-            return now;
-        }
-        if now.load(&format!("../../../../../images/{}", filename)) {
-            return now;
-        }
-        if now.load(&format!("../../../../../../images/{}", filename)) {
-            return now;
+        for prefix in [
+            "images",
+            "../images",
+            "../../images",
+            "../../../images",
+            "../../../../images",
+            "../../../../../images",
+            "../../../../../../images",
+        ] {
+            if now.load(&format!("{}/{}", prefix, filename)) {
+                return Some(now);
+            }
         }
 
-        panic!("ERROR: Could not load image file \"{}\".", filename);
+        None
     }
 
     pub fn load(&mut self, filename: &str) -> bool {
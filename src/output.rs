@@ -0,0 +1,206 @@
+use crate::camera::aov::Aovs;
+use crate::camera::framebuffer::Framebuffer;
+use crate::rtweekend::color::{Color, ToneMapping};
+use console::style;
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec,
+    WritableImage, f16,
+};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, ImageResult, Rgb, Rgb32FImage};
+use std::path::Path;
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct OutputOptions {
+    // Store OpenEXR channels as 16-bit half floats instead of 32-bit floats.
+    pub half: bool,
+    // Add the coverage of the camera rays as an alpha channel to OpenEXR files.
+    pub alpha: bool,
+    // Used for 8-bit formats only, HDR formats store the untouched radiance.
+    pub tone_mapping: ToneMapping,
+}
+
+// Writes the framebuffer to `path`, the format is chosen by the file extension. OpenEXR and
+// Radiance HDR files keep the linear radiance, every other format is tone mapped to 8 bits.
+// AOVs recorded in the framebuffer become extra layers of an OpenEXR file, other formats get
+// one OpenEXR file per AOV next to them, e.g. `image.albedo.exr` for `image.png`.
+pub(crate) fn save_image(fb: &Framebuffer, path: &Path, options: OutputOptions) -> ImageResult<()> {
+    if let Some(prefix) = path.parent() {
+        std::fs::create_dir_all(prefix)?;
+    }
+
+    println!("Output image as \"{}\"", style(path.display()).yellow());
+    let format = ImageFormat::from_path(path)?;
+    match format {
+        ImageFormat::OpenExr if fb.has_aovs() => save_exr_layers(fb, path, options),
+        ImageFormat::OpenExr => save_exr(fb, path, options),
+        ImageFormat::Hdr => save_hdr(fb, path),
+        _ => fb.to_rgb8(&options.tone_mapping).save(path),
+    }?;
+
+    if fb.has_aovs() && format != ImageFormat::OpenExr {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        for (name, channels) in aov_layers(fb, options.half) {
+            let aov_path = path.with_file_name(format!("{}.{}.exr", stem, name));
+            println!(
+                "Output {} as \"{}\"",
+                name,
+                style(aov_path.display()).yellow()
+            );
+            write_channels(fb, &aov_path, channels)?;
+        }
+    }
+    Ok(())
+}
+
+// NaN and infinite samples would poison every downstream tool, so NaN is written as black and
+// infinities as the largest finite value.
+fn finite(c: Color) -> [f32; 3] {
+    [c.x, c.y, c.z].map(|x| {
+        if x.is_nan() {
+            0.0
+        } else {
+            (x as f32).clamp(-f32::MAX, f32::MAX)
+        }
+    })
+}
+
+// Values past the half range would round to infinity, they are clamped like in finite.
+fn to_half(x: f32) -> f16 {
+    f16::from_f32(x.clamp(-f16::MAX.to_f32(), f16::MAX.to_f32()))
+}
+
+fn save_hdr(fb: &Framebuffer, path: &Path) -> ImageResult<()> {
+    let img = Rgb32FImage::from_fn(fb.width(), fb.height(), |x, y| Rgb(finite(fb.pixel(x, y))));
+    img.save(path)
+}
+
+fn save_exr(fb: &Framebuffer, path: &Path, options: OutputOptions) -> ImageResult<()> {
+    let (width, height) = (fb.width() as usize, fb.height() as usize);
+    let rgb = |x: usize, y: usize| finite(fb.pixel(x as u32, y as u32));
+    let alpha = |x: usize, y: usize| fb.alpha(x as u32, y as u32) as f32;
+
+    let result = match (options.half, options.alpha) {
+        (false, false) => exr::prelude::write_rgb_file(path, width, height, |x, y| {
+            let [r, g, b] = rgb(x, y);
+            (r, g, b)
+        }),
+        (false, true) => exr::prelude::write_rgba_file(path, width, height, |x, y| {
+            let [r, g, b] = rgb(x, y);
+            (r, g, b, alpha(x, y))
+        }),
+        (true, false) => exr::prelude::write_rgb_file(path, width, height, |x, y| {
+            let [r, g, b] = rgb(x, y).map(to_half);
+            (r, g, b)
+        }),
+        (true, true) => exr::prelude::write_rgba_file(path, width, height, |x, y| {
+            let [r, g, b] = rgb(x, y).map(to_half);
+            (r, g, b, to_half(alpha(x, y)))
+        }),
+    };
+
+    result.map_err(exr_error)
+}
+
+fn exr_error(err: exr::error::Error) -> ImageError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::OpenExr),
+        err,
+    ))
+}
+
+fn float_channel(name: &str, values: Vec<f32>, half: bool) -> AnyChannel<FlatSamples> {
+    let samples = if half {
+        FlatSamples::F16(values.into_iter().map(to_half).collect())
+    } else {
+        FlatSamples::F32(values)
+    };
+    AnyChannel::new(name, samples)
+}
+
+// Every AOV with its channels, named the way compositing tools expect them. The ids are stored
+// as unsigned integers.
+fn aov_layers(fb: &Framebuffer, half: bool) -> Vec<(&'static str, Vec<AnyChannel<FlatSamples>>)> {
+    let aovs: Vec<Aovs> = (0..fb.height())
+        .flat_map(|y| (0..fb.width()).map(move |x| fb.aovs(x, y)))
+        .collect();
+    let float = |name: &str, f: fn(&Aovs) -> f64| {
+        float_channel(name, aovs.iter().map(|a| f(a) as f32).collect(), half)
+    };
+    let id =
+        |f: fn(&Aovs) -> u32| AnyChannel::new("id", FlatSamples::U32(aovs.iter().map(f).collect()));
+
+    vec![
+        (
+            "albedo",
+            vec![
+                float("R", |a| a.albedo.x),
+                float("G", |a| a.albedo.y),
+                float("B", |a| a.albedo.z),
+            ],
+        ),
+        (
+            "normal",
+            vec![
+                float("X", |a| a.normal.x),
+                float("Y", |a| a.normal.y),
+                float("Z", |a| a.normal.z),
+            ],
+        ),
+        (
+            "position",
+            vec![
+                float("X", |a| a.position.x),
+                float("Y", |a| a.position.y),
+                float("Z", |a| a.position.z),
+            ],
+        ),
+        ("depth", vec![float("Z", |a| a.depth)]),
+        ("object_id", vec![id(|a| a.object_id)]),
+        ("material_id", vec![id(|a| a.material_id)]),
+    ]
+}
+
+fn write_channels(
+    fb: &Framebuffer,
+    path: &Path,
+    channels: Vec<AnyChannel<FlatSamples>>,
+) -> ImageResult<()> {
+    let layer = Layer::new(
+        (fb.width() as usize, fb.height() as usize),
+        LayerAttributes::default(),
+        Encoding::FAST_LOSSLESS,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(exr_error)
+}
+
+// The beauty pass as the usual R, G, B (and A) channels plus the AOVs as `albedo.R`,
+// `normal.X`, ... in a single layer, which is how multi-pass renders are usually exchanged.
+fn save_exr_layers(fb: &Framebuffer, path: &Path, options: OutputOptions) -> ImageResult<()> {
+    let pixels: Vec<[f32; 3]> = (0..fb.height())
+        .flat_map(|y| (0..fb.width()).map(move |x| finite(fb.pixel(x, y))))
+        .collect();
+    let mut channels: Vec<_> = ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(c, name)| float_channel(name, pixels.iter().map(|p| p[c]).collect(), options.half))
+        .collect();
+    if options.alpha {
+        let alpha = (0..fb.height())
+            .flat_map(|y| (0..fb.width()).map(move |x| fb.alpha(x, y) as f32))
+            .collect();
+        channels.push(float_channel("A", alpha, options.half));
+    }
+
+    for (layer, aov_channels) in aov_layers(fb, options.half) {
+        for mut channel in aov_channels {
+            channel.name = format!("{}.{}", layer, channel.name).as_str().into();
+            channels.push(channel);
+        }
+    }
+    write_channels(fb, path, channels)
+}
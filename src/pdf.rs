@@ -1,9 +1,10 @@
 use crate::material::hittable::Hittable;
 use crate::material::onb::Onb;
+use crate::rtweekend::PI;
+use crate::rtweekend::sampler::{self, Dimension};
 use crate::rtweekend::vec3::{
-    Point3, Vec3, dot, random_cosine_direction, random_unit_vector, unit_vector,
+    Point3, Vec3, dot, sample_cosine_direction, sample_unit_sphere, unit_vector,
 };
-use crate::rtweekend::{PI, random_double};
 use std::sync::Arc;
 
 pub trait Pdf: Send + Sync {
@@ -21,7 +22,8 @@ impl Pdf for SpherePdf {
     }
 
     fn generate(&self) -> Vec3 {
-        random_unit_vector()
+        let (u, v) = sampler::sample_2d(Dimension::Bsdf);
+        sample_unit_sphere(u, v)
     }
 }
 
@@ -42,7 +44,8 @@ impl Pdf for CosinePdf {
     }
 
     fn generate(&self) -> Vec3 {
-        self.uvw.transform(&random_cosine_direction())
+        let (r1, r2) = sampler::sample_2d(Dimension::Bsdf);
+        self.uvw.transform(&sample_cosine_direction(r1, r2))
     }
 }
 
@@ -83,7 +86,7 @@ impl Pdf for MixturePdf {
     }
 
     fn generate(&self) -> Vec3 {
-        if random_double() < 0.5 {
+        if sampler::sample_1d(Dimension::Strategy) < 0.5 {
             self.p[0].generate()
         } else {
             self.p[1].generate()
@@ -1,7 +1,15 @@
 pub(crate) mod color;
 pub(crate) mod interval;
+pub(crate) mod matrix;
+pub(crate) mod sampler;
 pub mod vec3;
 
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
 // const INFINITY:f64 = f64::INFINITY;
 pub(crate) const PI: f64 = std::f64::consts::PI;
 
@@ -9,14 +17,51 @@ pub(crate) fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+static SEED: OnceLock<u64> = OnceLock::new();
+static STREAM: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // Every thread owns a generator, each new thread takes the next stream derived from the
+    // seed. The camera reseeds it before every sample, see seed_sample.
+    static RNG: RefCell<SmallRng> = RefCell::new({
+        let stream = STREAM.fetch_add(1, Ordering::Relaxed);
+        SmallRng::seed_from_u64(seed() ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    });
+}
+
+pub(crate) fn set_seed(seed: u64) {
+    // Must be called before the first random number is drawn to take effect on every thread.
+    let _ = SEED.set(seed);
+}
+
+// Without an explicit seed every run draws one from the OS entropy source.
+pub(crate) fn seed() -> u64 {
+    *SEED.get_or_init(rand::random)
+}
+
+// SplitMix64 finalizer, spreads neighbouring inputs over the whole range.
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Restarts the generator of this thread from a state that only depends on the seed, the pixel
+// and the sample index. Every random number of a sample is then the same no matter which
+// thread takes it or in which order, so a seeded render is reproducible with any thread count.
+pub(crate) fn seed_sample(pixel: u64, sample: u64) {
+    let state = mix(seed() ^ mix(pixel ^ mix(sample)));
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(state));
+}
+
 pub(crate) fn random_double() -> f64 {
     //Return a random real in [0,1)
-    rand::random::<f64>()
+    RNG.with(|rng| rng.borrow_mut().random::<f64>())
 }
 
 pub(crate) fn random_double_range(min: f64, max: f64) -> f64 {
     //Return a random real in [min,max)
-    rand::random::<f64>() * (max - min) + min
+    random_double() * (max - min) + min
 }
 
 pub(crate) fn random_int_range(min: i32, max: i32) -> i32 {
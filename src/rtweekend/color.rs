@@ -1,9 +1,127 @@
 use crate::rtweekend::interval::Interval;
 use crate::rtweekend::vec3::Vec3;
 use image::Rgb;
+use std::str::FromStr;
 
 pub(crate) type Color = Vec3;
-pub(crate) fn write_color(pixel: &mut Rgb<u8>, pixel_color: &Color) {
+
+pub(crate) fn luminance(c: &Color) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+// Curve that maps scene-referred radiance into the displayable [0,1] range.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) enum ToneMap {
+    #[default]
+    Clamp,
+    Reinhard,
+    ReinhardExtended,
+    Aces,
+    Agx,
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "reinhard-extended" => Ok(ToneMap::ReinhardExtended),
+            "aces" => Ok(ToneMap::Aces),
+            "agx" => Ok(ToneMap::Agx),
+            _ => Err(format!(
+                "unknown tone mapping \"{}\", expected clamp, reinhard, reinhard-extended, aces or agx",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct ToneMapping {
+    pub operator: ToneMap,
+    // Exposure compensation in stops, the radiance is scaled by 2^exposure.
+    pub exposure: f64,
+    // Smallest radiance mapped to pure white by the extended Reinhard curve.
+    pub white_point: f64,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMap::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+        }
+    }
+}
+
+impl ToneMapping {
+    // Maps linear radiance to linear display values in [0,1].
+    pub(crate) fn apply(&self, c: Color) -> Color {
+        let c = c * 2f64.powf(self.exposure);
+        match self.operator {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => per_channel(c, |x| x / (1.0 + x)),
+            ToneMap::ReinhardExtended => {
+                let white_sq = self.white_point * self.white_point;
+                per_channel(c, |x| x * (1.0 + x / white_sq) / (1.0 + x))
+            }
+            ToneMap::Aces => per_channel(c, aces_filmic),
+            ToneMap::Agx => agx(c),
+        }
+    }
+}
+
+fn per_channel(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(c.x.max(0.0)), f(c.y.max(0.0)), f(c.z.max(0.0)))
+}
+
+fn aces_filmic(x: f64) -> f64 {
+    // Krzysztof Narkowicz's fit of the ACES reference rendering transform. The fit was made for
+    // inputs pre-exposed by 0.6, which keeps mid grey where the other curves put it.
+    let x = 0.6 * x;
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn mat3_mul(m: &[[f64; 3]; 3], c: Color) -> Color {
+    Color::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z,
+    )
+}
+
+fn agx(c: Color) -> Color {
+    // A minimal AgX: inset the primaries, take log2 over a fixed dynamic range, apply the
+    // default contrast sigmoid (as a polynomial fit) and outset the primaries again.
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    let c = mat3_mul(&INSET, c);
+    let c = per_channel(c, |x| {
+        let x = (x.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // The sigmoid output is display encoded with a 2.2 gamma, undo it to stay linear.
+    per_channel(mat3_mul(&OUTSET, c), |x| x.powf(2.2))
+}
+
+pub(crate) fn write_color(pixel: &mut Rgb<u8>, pixel_color: &Color, tone_mapping: &ToneMapping) {
     let mut r = pixel_color.x;
     let mut g = pixel_color.y;
     let mut b = pixel_color.z;
@@ -18,9 +136,11 @@ pub(crate) fn write_color(pixel: &mut Rgb<u8>, pixel_color: &Color) {
         b = 0.0;
     }
 
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+    let mapped = tone_mapping.apply(Color::new(r, g, b));
+
+    r = linear_to_srgb(mapped.x);
+    g = linear_to_srgb(mapped.y);
+    b = linear_to_srgb(mapped.z);
 
     //Translate the [0,1] component values to the byte range [0,255].
     let intensity: Interval = Interval::new(0.0, 0.999);
@@ -31,9 +151,12 @@ pub(crate) fn write_color(pixel: &mut Rgb<u8>, pixel_color: &Color) {
     *pixel = Rgb([rbyte, gbyte, bbyte]);
 }
 
-pub fn linear_to_gamma(linear_component: f64) -> f64 {
-    if linear_component > 0.0 {
-        return linear_component.sqrt();
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0.0 {
+        0.0
+    } else if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
     }
-    0.0
 }
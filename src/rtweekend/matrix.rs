@@ -0,0 +1,228 @@
+use crate::rtweekend::degrees_to_radians;
+use crate::rtweekend::vec3::{Point3, Vec3, cross, unit_vector};
+use std::ops::Mul;
+
+// Affine transform as a row-major 4x4 matrix acting on column vectors. The helpers that take a
+// matrix (rotate, scale, translate, ...) apply their transform after it, so
+// `Mat4::IDENTITY.scale(s).rotate_y(a).translate(t)` scales first and translates last.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub(crate) const IDENTITY: Mat4 = Mat4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    // The upper 3x3 part and the translation column.
+    fn affine(l: [[f64; 3]; 3], t: Vec3) -> Mat4 {
+        Mat4 {
+            m: [
+                [l[0][0], l[0][1], l[0][2], t.x],
+                [l[1][0], l[1][1], l[1][2], t.y],
+                [l[2][0], l[2][1], l[2][2], t.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub(crate) fn translation(offset: Vec3) -> Mat4 {
+        Mat4::affine([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], offset)
+    }
+
+    // Negative factors mirror the object.
+    pub(crate) fn scaling(factors: Vec3) -> Mat4 {
+        Mat4::affine(
+            [
+                [factors.x, 0.0, 0.0],
+                [0.0, factors.y, 0.0],
+                [0.0, 0.0, factors.z],
+            ],
+            Vec3::default(),
+        )
+    }
+
+    // Counterclockwise rotation by `angle` degrees when looking down the axis (Rodrigues).
+    pub(crate) fn rotation(axis: Vec3, angle: f64) -> Mat4 {
+        let a = unit_vector(&axis);
+        let (sin, cos) = degrees_to_radians(angle).sin_cos();
+        let t = 1.0 - cos;
+        Mat4::affine(
+            [
+                [
+                    t * a.x * a.x + cos,
+                    t * a.x * a.y - sin * a.z,
+                    t * a.x * a.z + sin * a.y,
+                ],
+                [
+                    t * a.x * a.y + sin * a.z,
+                    t * a.y * a.y + cos,
+                    t * a.y * a.z - sin * a.x,
+                ],
+                [
+                    t * a.x * a.z - sin * a.y,
+                    t * a.y * a.z + sin * a.x,
+                    t * a.z * a.z + cos,
+                ],
+            ],
+            Vec3::default(),
+        )
+    }
+
+    // Rotation about x, then y, then z, all in degrees.
+    pub(crate) fn euler(angles: Vec3) -> Mat4 {
+        Mat4::rotation(Vec3::new(0.0, 0.0, 1.0), angles.z)
+            * Mat4::rotation(Vec3::new(0.0, 1.0, 0.0), angles.y)
+            * Mat4::rotation(Vec3::new(1.0, 0.0, 0.0), angles.x)
+    }
+
+    // Rotation given by the quaternion w + xi + yj + zk, which doesn't need to be normalized.
+    pub(crate) fn quaternion(w: f64, x: f64, y: f64, z: f64) -> Mat4 {
+        let n = (w * w + x * x + y * y + z * z).sqrt();
+        let (w, x, y, z) = (w / n, x / n, y / n, z / n);
+        Mat4::affine(
+            [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                ],
+            ],
+            Vec3::default(),
+        )
+    }
+
+    // Places an object at `from` with its -z axis pointing at `to` and its y axis towards `up`,
+    // the way the camera is oriented.
+    pub(crate) fn look_at(from: Point3, to: Point3, up: Vec3) -> Mat4 {
+        let w = unit_vector(&(from - to));
+        let u = unit_vector(&cross(&up, &w));
+        let v = cross(&w, &u);
+        Mat4::affine([[u.x, v.x, w.x], [u.y, v.y, w.y], [u.z, v.z, w.z]], from)
+    }
+
+    pub(crate) fn translate(self, offset: Vec3) -> Mat4 {
+        Mat4::translation(offset) * self
+    }
+
+    pub(crate) fn scale(self, factors: Vec3) -> Mat4 {
+        Mat4::scaling(factors) * self
+    }
+
+    pub(crate) fn rotate(self, axis: Vec3, angle: f64) -> Mat4 {
+        Mat4::rotation(axis, angle) * self
+    }
+
+    pub(crate) fn rotate_x(self, angle: f64) -> Mat4 {
+        self.rotate(Vec3::new(1.0, 0.0, 0.0), angle)
+    }
+
+    pub(crate) fn rotate_y(self, angle: f64) -> Mat4 {
+        self.rotate(Vec3::new(0.0, 1.0, 0.0), angle)
+    }
+
+    pub(crate) fn rotate_z(self, angle: f64) -> Mat4 {
+        self.rotate(Vec3::new(0.0, 0.0, 1.0), angle)
+    }
+
+    // Determinant of the upper 3x3 part, how much the transform scales volumes.
+    pub(crate) fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None for singular matrices.
+    pub(crate) fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::IDENTITY.m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= p;
+                inv[col][j] /= p;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    pub(crate) fn transform_point(&self, p: &Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    // Directions and tangents ignore the translation.
+    pub(crate) fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    // Normals go through the inverse transpose, so this is called on the inverse matrix. The
+    // result isn't normalized.
+    pub(crate) fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::IDENTITY
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
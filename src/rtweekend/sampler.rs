@@ -0,0 +1,500 @@
+use crate::rtweekend::{self, random_double};
+use std::cell::RefCell;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+// Hands out the coordinates of the sample vector of one camera sample. Coordinate `dim` of
+// sample `index` of a pixel only depends on those three values (and the seed), so pixels can be
+// rendered in any order and on any thread.
+pub(crate) trait Sampler: Send + Sync {
+    fn sample_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64;
+
+    // Two coordinates used together, e.g. a point on the lens. Samplers that stratify in two
+    // dimensions override this.
+    fn sample_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        (
+            self.sample_1d(pixel, index, dim),
+            self.sample_1d(pixel, index, dim + 1),
+        )
+    }
+}
+
+// What a coordinate of the sample vector is used for. The camera takes the first dimensions and
+// every bounce gets a block of its own, so a purpose always lands on the same dimension and the
+// well distributed first dimensions go to the decisions that matter most.
+#[derive(Clone, Copy)]
+pub(crate) enum Dimension {
+    Pixel,       // 2D position in the pixel footprint
+    Lens,        // 2D point on the defocus disk
+    Time,        // shutter time
+    Bsdf,        // 2D direction sampled from the material
+    Lobe,        // reflection or refraction
+    Light,       // 2D point on a light
+    LightSelect, // which light to sample
+    Strategy,    // sample the lights or the material
+    Medium,      // scattering distance in participating media
+    Roulette,    // whether a path survives Russian roulette
+    // Light, LightSelect and Medium while sampling the lights for next event estimation, so the
+    // shadow ray doesn't repeat the choices of the path itself.
+    NeeLight,
+    NeeLightSelect,
+    NeeMedium,
+}
+
+const CAMERA_DIMENSIONS: u32 = 5;
+const BOUNCE_DIMENSIONS: u32 = 13;
+
+impl Dimension {
+    fn index(self, bounce: u32) -> u32 {
+        let start = CAMERA_DIMENSIONS + bounce * BOUNCE_DIMENSIONS;
+        match self {
+            Dimension::Pixel => 0,
+            Dimension::Lens => 2,
+            Dimension::Time => 4,
+            Dimension::Bsdf => start,
+            Dimension::Lobe => start + 2,
+            Dimension::Light => start + 3,
+            Dimension::LightSelect => start + 5,
+            Dimension::Strategy => start + 6,
+            Dimension::Medium => start + 7,
+            Dimension::Roulette => start + 8,
+            Dimension::NeeLight => start + 9,
+            Dimension::NeeLightSelect => start + 11,
+            Dimension::NeeMedium => start + 12,
+        }
+    }
+
+    fn next_event(self) -> Dimension {
+        match self {
+            Dimension::Light => Dimension::NeeLight,
+            Dimension::LightSelect => Dimension::NeeLightSelect,
+            Dimension::Medium => Dimension::NeeMedium,
+            dim => dim,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum SamplerKind {
+    Independent,
+    #[default]
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "blue-noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!(
+                "unknown sampler \"{}\", expected independent, stratified, halton, sobol or blue-noise",
+                s
+            )),
+        }
+    }
+}
+
+impl SamplerKind {
+    // `sqrt_n` squared samples are taken per pixel and pass, the stratified sampler splits
+    // every pass into that many strata.
+    pub(crate) fn build(self, sqrt_n: u32) -> Arc<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Arc::new(Independent),
+            SamplerKind::Stratified => Arc::new(Stratified { sqrt_n }),
+            SamplerKind::Halton => Arc::new(Halton),
+            SamplerKind::Sobol => Arc::new(Sobol),
+            SamplerKind::BlueNoise => Arc::new(BlueNoise),
+        }
+    }
+}
+
+struct State {
+    sampler: Option<Arc<dyn Sampler>>,
+    pixel: (u32, u32),
+    index: u32,
+    bounce: u32,
+    // What is left of the light selection coordinate while a chosen light samples itself.
+    light_select: Option<f64>,
+    next_event: bool,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = const {
+        RefCell::new(State {
+            sampler: None,
+            pixel: (0, 0),
+            index: 0,
+            bounce: 0,
+            light_select: None,
+            next_event: false,
+        })
+    };
+}
+
+// Called by the camera before tracing a sample, the following sample_1d/sample_2d calls on this
+// thread draw from it.
+pub(crate) fn start_sample(sampler: &Arc<dyn Sampler>, pixel: (u32, u32), index: u32) {
+    rtweekend::seed_sample((pixel.1 as u64) << 32 | pixel.0 as u64, index as u64);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state
+            .sampler
+            .as_ref()
+            .is_some_and(|current| Arc::ptr_eq(current, sampler))
+        {
+            state.sampler = Some(sampler.clone());
+        }
+        state.pixel = pixel;
+        state.index = index;
+        state.bounce = 0;
+        state.light_select = None;
+        state.next_event = false;
+    });
+}
+
+// Selects the block of dimensions used at the given path vertex.
+pub(crate) fn start_bounce(bounce: u32) {
+    STATE.with(|state| state.borrow_mut().bounce = bounce);
+}
+
+// Runs `f`, the light sample and shadow ray of next event estimation, on the dimensions set
+// aside for it.
+pub(crate) fn next_event<T>(f: impl FnOnce() -> T) -> T {
+    let outer = STATE.with(|state| std::mem::replace(&mut state.borrow_mut().next_event, true));
+    let result = f();
+    STATE.with(|state| state.borrow_mut().next_event = outer);
+    result
+}
+
+impl State {
+    fn dimension(&self, dim: Dimension) -> u32 {
+        let dim = if self.next_event {
+            dim.next_event()
+        } else {
+            dim
+        };
+        dim.index(self.bounce)
+    }
+}
+
+pub(crate) fn sample_1d(dim: Dimension) -> f64 {
+    STATE.with(|state| {
+        let state = state.borrow();
+        match &state.sampler {
+            Some(sampler) => sampler.sample_1d(state.pixel, state.index, state.dimension(dim)),
+            None => random_double(),
+        }
+    })
+}
+
+// Picks one of `n` lights and samples it with `sample`. Lists of lights nested in the chosen one
+// pick with the remainder of the same coordinate, so their choice doesn't follow the outer one.
+pub(crate) fn select_light<T>(n: usize, sample: impl FnOnce(usize) -> T) -> T {
+    let u = STATE
+        .with(|state| state.borrow_mut().light_select.take())
+        .unwrap_or_else(|| sample_1d(Dimension::LightSelect))
+        * n as f64;
+    let index = (u as usize).min(n - 1);
+    STATE.with(|state| {
+        state.borrow_mut().light_select = Some((u - index as f64).min(1.0 - f64::EPSILON));
+    });
+    let result = sample(index);
+    STATE.with(|state| state.borrow_mut().light_select = None);
+    result
+}
+
+pub(crate) fn sample_2d(dim: Dimension) -> (f64, f64) {
+    STATE.with(|state| {
+        let state = state.borrow();
+        match &state.sampler {
+            Some(sampler) => sampler.sample_2d(state.pixel, state.index, state.dimension(dim)),
+            None => (random_double(), random_double()),
+        }
+    })
+}
+
+fn hash(values: &[u64]) -> u64 {
+    values
+        .iter()
+        .fold(rtweekend::seed(), |h, &v| rtweekend::mix(h ^ v))
+}
+
+// Top 53 bits of a hash as a double in [0,1).
+fn unit_from_hash(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn unit_from_bits(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+// Uniform random numbers, drawn in call order from the generator of the sample.
+struct Independent;
+
+impl Sampler for Independent {
+    fn sample_1d(&self, _pixel: (u32, u32), _index: u32, _dim: u32) -> f64 {
+        random_double()
+    }
+}
+
+// Jittered strata: every pass of n samples puts one sample into each of the n strata of a
+// dimension (of the sqrt(n) x sqrt(n) cells for a pair). The strata are visited in a shuffled
+// order that differs per pixel, dimension and pass, so dimensions don't correlate.
+struct Stratified {
+    sqrt_n: u32,
+}
+
+impl Sampler for Stratified {
+    fn sample_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        let n = self.sqrt_n * self.sqrt_n;
+        let h = hash(&[
+            pixel.0 as u64,
+            pixel.1 as u64,
+            dim as u64,
+            (index / n) as u64,
+        ]);
+        let stratum = permute(index % n, n, h as u32);
+        (stratum as f64 + unit_from_hash(rtweekend::mix(h ^ index as u64))) / n as f64
+    }
+
+    fn sample_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        let n = self.sqrt_n * self.sqrt_n;
+        let h = hash(&[
+            pixel.0 as u64,
+            pixel.1 as u64,
+            dim as u64,
+            (index / n) as u64,
+        ]);
+        let cell = permute(index % n, n, h as u32);
+        let jitter_x = unit_from_hash(rtweekend::mix(h ^ index as u64));
+        let jitter_y = unit_from_hash(rtweekend::mix(h ^ !(index as u64)));
+        (
+            ((cell % self.sqrt_n) as f64 + jitter_x) / self.sqrt_n as f64,
+            ((cell / self.sqrt_n) as f64 + jitter_y) / self.sqrt_n as f64,
+        )
+    }
+}
+
+// Element i of a pseudo random permutation of 0..l (Kensler, Correlated Multi-Jittered
+// Sampling).
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i + p) % l
+}
+
+// The Halton sequence, dimension d uses the d-th prime as base. Every pixel and dimension
+// permutes the digits in its own random way: unscrambled, the large bases put the first samples
+// of a pixel on a short ramp instead of spreading them out.
+struct Halton;
+
+impl Sampler for Halton {
+    fn sample_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        let seed = hash(&[pixel.0 as u64, pixel.1 as u64, dim as u64]);
+        match primes().get(dim as usize) {
+            Some(&base) => scrambled_radical_inverse(base, index, seed),
+            None => unit_from_hash(rtweekend::mix(seed ^ index as u64)),
+        }
+    }
+}
+
+fn primes() -> &'static [u32] {
+    static PRIMES: OnceLock<Vec<u32>> = OnceLock::new();
+    PRIMES.get_or_init(|| {
+        let mut primes: Vec<u32> = Vec::new();
+        let mut candidate = 2;
+        while primes.len() < 1024 {
+            if primes
+                .iter()
+                .take_while(|&&p| p * p <= candidate)
+                .all(|&p| candidate % p != 0)
+            {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+        primes
+    })
+}
+
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut scale = inv_base;
+    let mut result = 0.0;
+    let mut digit_seed = seed;
+    while index > 0 {
+        digit_seed = rtweekend::mix(digit_seed);
+        result += permute(index % base, base, digit_seed as u32) as f64 * scale;
+        index /= base;
+        scale *= inv_base;
+    }
+    // The permuted trailing zero digits add up to a random offset below the last digit.
+    result += unit_from_hash(rtweekend::mix(digit_seed ^ 1)) * scale * base as f64;
+    result.min(1.0 - f64::EPSILON / 2.0)
+}
+
+fn wrap(x: f64) -> f64 {
+    if x >= 1.0 { x - 1.0 } else { x }
+}
+
+// Owen-scrambled Sobol points, following Burley's Practical Hash-based Owen Scrambling: every
+// pair of dimensions is a 2D Sobol sequence, scrambled and shuffled with its own seed per pixel.
+struct Sobol;
+
+impl Sampler for Sobol {
+    fn sample_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        let seed = hash(&[pixel.0 as u64, pixel.1 as u64, dim as u64]);
+        owen_sobol(index, seed).0
+    }
+
+    fn sample_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        let seed = hash(&[pixel.0 as u64, pixel.1 as u64, dim as u64]);
+        owen_sobol(index, seed)
+    }
+}
+
+fn owen_sobol(index: u32, seed: u64) -> (f64, f64) {
+    let index = nested_uniform_scramble(index, seed as u32);
+    let seed = rtweekend::mix(seed);
+    (
+        unit_from_bits(nested_uniform_scramble(index.reverse_bits(), seed as u32)),
+        unit_from_bits(nested_uniform_scramble(
+            sobol_second_dimension(index),
+            (seed >> 32) as u32,
+        )),
+    )
+}
+
+// The generator matrix of the second Sobol dimension is the Pascal matrix mod 2, so its
+// direction numbers follow from v ^ (v >> 1).
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut v = 1 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+// Blue-noise dithered sampling (Georgiev and Fajardo): all pixels share one scrambled Sobol
+// sequence per dimension, offset by a blue-noise mask. Neighbouring pixels then get very
+// different offsets and the remaining error is pushed to high frequencies.
+struct BlueNoise;
+
+impl BlueNoise {
+    fn offset(pixel: (u32, u32), dim: u32) -> f64 {
+        // Each dimension reads the tiled mask at its own toroidal shift.
+        let shift = hash(&[dim as u64]);
+        let x = (pixel.0 as usize + shift as usize) % MASK_SIZE;
+        let y = (pixel.1 as usize + (shift >> 32) as usize) % MASK_SIZE;
+        blue_noise_mask()[y * MASK_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoise {
+    fn sample_1d(&self, pixel: (u32, u32), index: u32, dim: u32) -> f64 {
+        let (x, _) = owen_sobol(index, hash(&[dim as u64]));
+        wrap(x + BlueNoise::offset(pixel, dim))
+    }
+
+    fn sample_2d(&self, pixel: (u32, u32), index: u32, dim: u32) -> (f64, f64) {
+        let (x, y) = owen_sobol(index, hash(&[dim as u64]));
+        (
+            wrap(x + BlueNoise::offset(pixel, dim)),
+            wrap(y + BlueNoise::offset(pixel, dim + 1)),
+        )
+    }
+}
+
+const MASK_SIZE: usize = 64;
+
+// A tileable blue-noise mask with values in [0,1). It is built like the void-and-cluster
+// method: points are added one at a time into the largest void, the cell with the least energy
+// from the points placed so far under a Gaussian kernel, and their order becomes the value.
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        const SIGMA: f64 = 1.5;
+        let n = MASK_SIZE * MASK_SIZE;
+        let kernel: Vec<f64> = (0..n)
+            .map(|k| {
+                let dx = (k % MASK_SIZE).min(MASK_SIZE - k % MASK_SIZE) as f64;
+                let dy = (k / MASK_SIZE).min(MASK_SIZE - k / MASK_SIZE) as f64;
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+
+        // A tiny fixed jitter breaks the ties of the empty mask, the mask is the same every run.
+        let mut energy: Vec<f64> = (0..n)
+            .map(|k| 1e-9 * unit_from_hash(rtweekend::mix(k as u64)))
+            .collect();
+        let mut mask = vec![-1.0; n];
+        for rank in 0..n {
+            let void = (0..n)
+                .filter(|&k| mask[k] < 0.0)
+                .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+                .unwrap();
+            mask[void] = (rank as f64 + 0.5) / n as f64;
+
+            let (vx, vy) = (void % MASK_SIZE, void / MASK_SIZE);
+            for (k, e) in energy.iter_mut().enumerate() {
+                let dx = (k % MASK_SIZE + MASK_SIZE - vx) % MASK_SIZE;
+                let dy = (k / MASK_SIZE + MASK_SIZE - vy) % MASK_SIZE;
+                *e += kernel[dy * MASK_SIZE + dx];
+            }
+        }
+        mask
+    })
+}
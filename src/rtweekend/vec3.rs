@@ -147,33 +147,31 @@ pub fn unit_vector(v: &Vec3) -> Vec3 {
     }
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = Vec3::new(
-            random_double_range(-1.0, 1.0),
-            random_double_range(-1.0, 1.0),
-            0.0,
-        );
-        if p.length_squared() < 1.0 {
-            return p;
-        }
-    }
+// Maps a point of the unit square to the unit disk (Shirley and Chiu's concentric mapping),
+// which keeps the stratification of the square.
+pub fn sample_unit_disk(u: f64, v: f64) -> Vec3 {
+    let a = 2.0 * u - 1.0;
+    let b = 2.0 * v - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
-pub fn random_unit_vector() -> Vec3 {
-    loop {
-        let p = Vec3::random_range(-1.0, 1.0);
-        let lensq = p.length_squared();
-        if 1e-160 < lensq && lensq <= 1.0 {
-            return p / lensq.sqrt();
-        }
-    }
+// Maps a point of the unit square to a uniformly distributed direction.
+pub fn sample_unit_sphere(u: f64, v: f64) -> Vec3 {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }
 
-pub fn random_cosine_direction() -> Vec3 {
-    let r1 = random_double();
-    let r2 = random_double();
-
+pub fn sample_cosine_direction(r1: f64, r2: f64) -> Vec3 {
     let phi = 2.0 * PI * r1;
     let x = phi.cos() * r2.sqrt();
     let y = phi.sin() * r2.sqrt();
@@ -182,15 +180,6 @@ pub fn random_cosine_direction() -> Vec3 {
     Vec3::new(x, y, z)
 }
 
-pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
-    let on_unit_sphere = random_unit_vector();
-    if dot(&on_unit_sphere, &normal) > 0.0 {
-        on_unit_sphere
-    } else {
-        -on_unit_sphere
-    }
-}
-
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - 2f64 * dot(v, n) * *n
 }
@@ -0,0 +1,719 @@
+use crate::camera::Camera;
+use crate::material::hittable::Hittable;
+use crate::material::hittable::hittable_list::HittableList;
+use crate::material::hittable::instance::Instance;
+use crate::material::hittable::mesh::Mesh;
+use crate::material::hittable::motion::{Keyframe, MotionTransform};
+use crate::material::hittable::quad::{Quad, make_box};
+use crate::material::hittable::sphere::Sphere;
+use crate::material::hittable::transform::Transform;
+use crate::material::hittable::triangle::Triangle;
+use crate::material::texture::rtw_stb_image::RtwImage;
+use crate::material::texture::{ImageTexture, UV};
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Mapping, Material, Metal};
+use crate::rtweekend::color::Color;
+use crate::rtweekend::matrix::Mat4;
+use crate::rtweekend::vec3::{Vec3, cross, unit_vector};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// A scene file describes the camera, a table of named materials, the objects of the world and
+// the shapes that are importance-sampled as lights. Image and model paths are looked up under
+// `images/`, the same way the hard-coded scenes do.
+
+pub(crate) struct Scene {
+    pub world: HittableList,
+    pub lights: HittableList,
+    pub camera: Camera,
+}
+
+#[derive(Debug)]
+pub(crate) struct SceneError {
+    file: PathBuf,
+    entry: Option<String>,
+    message: String,
+}
+
+impl SceneError {
+    fn new(file: &Path, entry: Option<String>, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            entry,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.entry {
+            Some(entry) => write!(f, "{}: {}: {}", self.file.display(), entry, self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+type Triple = [f64; 3];
+
+fn vec3(t: Triple) -> Vec3 {
+    Vec3::new(t[0], t[1], t[2])
+}
+
+// The entries are read one at a time from generic values, so an error names the entry it is in
+// whatever the format of the file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: Option<Value>,
+    #[serde(default)]
+    materials: BTreeMap<String, Value>,
+    #[serde(default)]
+    objects: Vec<Value>,
+    #[serde(default)]
+    lights: Vec<Value>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDesc {
+    aspect_ratio: Option<f64>,
+    image_width: Option<u32>,
+    samples_per_pixel: Option<u32>,
+    max_depth: Option<i32>,
+    background: Option<Triple>,
+    vfov: Option<f64>,
+    lookfrom: Option<Triple>,
+    lookat: Option<Triple>,
+    vup: Option<Triple>,
+    defocus_angle: Option<f64>,
+    focus_dist: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: Option<Triple>,
+        texture: Option<String>,
+    },
+    Metal {
+        albedo: Triple,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        refraction_index: f64,
+    },
+    DiffuseLight {
+        emit: Triple,
+    },
+    Mapping {
+        base: Option<Box<MaterialDesc>>,
+        normal_map: Option<String>,
+        alpha_map: Option<String>,
+        light_map: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct ObjectDesc {
+    #[serde(flatten)]
+    shape: ShapeDesc,
+    #[serde(default)]
+    transform: Vec<TransformDesc>,
+    // Moves the object over the ray times, after its transforms.
+    #[serde(default)]
+    motion: Vec<KeyframeDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDesc {
+    Sphere {
+        center: Triple,
+        radius: f64,
+        // End point of a moving sphere, reached at ray time 1.0.
+        center2: Option<Triple>,
+        material: Option<String>,
+    },
+    Quad {
+        q: Triple,
+        u: Triple,
+        v: Triple,
+        material: Option<String>,
+    },
+    Triangle {
+        a: Triple,
+        b: Triple,
+        c: Triple,
+        material: Option<String>,
+    },
+    Box {
+        a: Triple,
+        b: Triple,
+        material: Option<String>,
+    },
+    Model {
+        obj: String,
+        mtl: String,
+        // The same model with its vertices where they are at ray time 1.0, the mesh deforms
+        // linearly into it.
+        obj2: Option<String>,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        rotate_y: f64,
+        #[serde(default)]
+        translate: Triple,
+        // Replaces the materials of the model file.
+        material: Option<String>,
+    },
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+// Applied in the order they are listed. Angles are in degrees.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate {
+        axis: Triple,
+        angle: f64,
+    },
+    // Rotation about x, then y, then z.
+    Euler(Triple),
+    // [w, x, y, z]
+    Quaternion([f64; 4]),
+    Scale(Triple),
+    Translate(Triple),
+    // Moves the object to `from` and turns its -z axis towards `to`.
+    LookAt {
+        from: Triple,
+        to: Triple,
+        #[serde(default = "default_up")]
+        up: Triple,
+    },
+}
+
+fn default_up() -> Triple {
+    [0.0, 1.0, 0.0]
+}
+
+// Pose of a moving object at a ray time in [0, 1], poses in between are interpolated. The object
+// is scaled, rotated by `angle` degrees about `axis` and translated.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    #[serde(default = "default_factors")]
+    scale: Triple,
+    #[serde(default = "default_axis")]
+    axis: Triple,
+    #[serde(default)]
+    angle: f64,
+    #[serde(default)]
+    translate: Triple,
+}
+
+fn default_factors() -> Triple {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_axis() -> Triple {
+    [0.0, 1.0, 0.0]
+}
+
+// Applies the transforms in order after `matrix`.
+fn transform_matrix(matrix: Mat4, transforms: &[TransformDesc]) -> Mat4 {
+    transforms
+        .iter()
+        .fold(matrix, |m, transform| match transform {
+            TransformDesc::RotateX(angle) => m.rotate_x(*angle),
+            TransformDesc::RotateY(angle) => m.rotate_y(*angle),
+            TransformDesc::RotateZ(angle) => m.rotate_z(*angle),
+            TransformDesc::Rotate { axis, angle } => m.rotate(vec3(*axis), *angle),
+            TransformDesc::Euler(angles) => Mat4::euler(vec3(*angles)) * m,
+            TransformDesc::Quaternion([w, x, y, z]) => Mat4::quaternion(*w, *x, *y, *z) * m,
+            TransformDesc::Scale(factors) => m.scale(vec3(*factors)),
+            TransformDesc::Translate(offset) => m.translate(vec3(*offset)),
+            TransformDesc::LookAt { from, to, up } => {
+                Mat4::look_at(vec3(*from), vec3(*to), vec3(*up)) * m
+            }
+        })
+}
+
+pub(crate) fn load_scene(path: &Path) -> Result<Scene, SceneError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| SceneError::new(path, None, format!("cannot read file: {}", err)))?;
+    parse_scene(path, &text)
+}
+
+// Builds the scene described by `text`, in the format given by the extension of `path`.
+fn parse_scene(path: &Path, text: &str) -> Result<Scene, SceneError> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let desc: SceneDesc = match extension.as_str() {
+        "json" => serde_json::from_str(text).map_err(|err| err.to_string()),
+        "yaml" | "yml" => serde_yaml::from_str(text).map_err(|err| err.to_string()),
+        _ => Err(format!(
+            "unknown scene format \".{}\", expected .json, .yaml or .yml",
+            extension
+        )),
+    }
+    .map_err(|message| SceneError::new(path, None, message))?;
+
+    SceneBuilder {
+        file: path,
+        materials: BTreeMap::new(),
+    }
+    .build(desc)
+}
+
+struct SceneBuilder<'a> {
+    file: &'a Path,
+    materials: BTreeMap<String, Arc<dyn Material>>,
+}
+
+impl SceneBuilder<'_> {
+    fn error(&self, entry: String, message: String) -> SceneError {
+        SceneError::new(self.file, Some(entry), message)
+    }
+
+    fn entry<T: DeserializeOwned>(&self, entry: &str, value: Value) -> Result<T, SceneError> {
+        serde_json::from_value(value).map_err(|err| self.error(entry.to_string(), err.to_string()))
+    }
+
+    fn build(mut self, desc: SceneDesc) -> Result<Scene, SceneError> {
+        let camera: CameraDesc = match desc.camera {
+            Some(value) => self.entry("camera", value)?,
+            None => CameraDesc::default(),
+        };
+
+        for (name, value) in desc.materials {
+            let entry = format!("materials.{}", name);
+            let mat = self.material(&entry, &self.entry(&entry, value)?)?;
+            self.materials.insert(name, mat);
+        }
+
+        let mut world = HittableList::new();
+        for (i, value) in desc.objects.into_iter().enumerate() {
+            let entry = format!("objects[{}]", i);
+            world.add(self.object(&entry, &self.entry(&entry, value)?, false)?);
+        }
+
+        if desc.lights.is_empty() {
+            return Err(SceneError::new(
+                self.file,
+                Some("lights".to_string()),
+                "at least one light shape is required for importance sampling".to_string(),
+            ));
+        }
+        let mut lights = HittableList::new();
+        for (i, value) in desc.lights.into_iter().enumerate() {
+            let entry = format!("lights[{}]", i);
+            lights.add(self.object(&entry, &self.entry(&entry, value)?, true)?);
+        }
+
+        Ok(Scene {
+            world,
+            lights,
+            camera: Self::camera(&camera),
+        })
+    }
+
+    fn camera(desc: &CameraDesc) -> Camera {
+        let mut cam = Camera::new();
+        if let Some(x) = desc.aspect_ratio {
+            cam.aspect_ratio = x;
+        }
+        if let Some(x) = desc.image_width {
+            cam.image_width = x;
+        }
+        if let Some(x) = desc.samples_per_pixel {
+            cam.samples_per_pixel = x;
+        }
+        if let Some(x) = desc.max_depth {
+            cam.max_depth = x;
+        }
+        if let Some(x) = desc.background {
+            cam.background = vec3(x);
+        }
+        if let Some(x) = desc.vfov {
+            cam.vfov = x;
+        }
+        if let Some(x) = desc.lookfrom {
+            cam.lookfrom = vec3(x);
+        }
+        if let Some(x) = desc.lookat {
+            cam.lookat = vec3(x);
+        }
+        if let Some(x) = desc.vup {
+            cam.vup = vec3(x);
+        }
+        if let Some(x) = desc.defocus_angle {
+            cam.defocus_angle = x;
+        }
+        if let Some(x) = desc.focus_dist {
+            cam.focus_dist = x;
+        }
+        cam
+    }
+
+    fn image(&self, entry: &str, filename: &str) -> Result<RtwImage, SceneError> {
+        RtwImage::open(filename).ok_or_else(|| {
+            self.error(
+                entry.to_string(),
+                format!("cannot load image \"{}\"", filename),
+            )
+        })
+    }
+
+    fn material(&self, entry: &str, desc: &MaterialDesc) -> Result<Arc<dyn Material>, SceneError> {
+        let mat: Arc<dyn Material> = match desc {
+            MaterialDesc::Lambertian { albedo, texture } => match (albedo, texture) {
+                (Some(_), Some(_)) => {
+                    return Err(self.error(
+                        entry.to_string(),
+                        "lambertian takes either \"albedo\" or \"texture\", not both".to_string(),
+                    ));
+                }
+                (_, Some(filename)) => Arc::new(Lambertian::new_tex(Arc::new(
+                    ImageTexture::new_image(self.image(entry, filename)?),
+                ))),
+                (Some(albedo), None) => Arc::new(Lambertian::new(&vec3(*albedo))),
+                (None, None) => Arc::new(Lambertian::default()),
+            },
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(&vec3(*albedo), *fuzz)),
+            MaterialDesc::Dielectric { refraction_index } => {
+                Arc::new(Dielectric::new(*refraction_index))
+            }
+            MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight::new_color(&vec3(*emit))),
+            MaterialDesc::Mapping {
+                base,
+                normal_map,
+                alpha_map,
+                light_map,
+            } => {
+                let mut mapping = match base {
+                    Some(base) => Mapping::new(self.material(&format!("{}.base", entry), base)?),
+                    None => Mapping::default(),
+                };
+                if let Some(filename) = normal_map {
+                    mapping.set_normal_mapping(self.image(entry, filename)?);
+                }
+                if let Some(filename) = alpha_map {
+                    mapping.set_alpha_mapping(self.image(entry, filename)?);
+                }
+                if let Some(filename) = light_map {
+                    mapping.set_light_mapping(self.image(entry, filename)?);
+                }
+                Arc::new(mapping)
+            }
+        };
+        Ok(mat)
+    }
+
+    fn lookup(
+        &self,
+        entry: &str,
+        name: &Option<String>,
+        is_light: bool,
+    ) -> Result<Arc<dyn Material>, SceneError> {
+        match name {
+            Some(name) => self.materials.get(name).cloned().ok_or_else(|| {
+                self.error(entry.to_string(), format!("unknown material \"{}\"", name))
+            }),
+            // Light shapes are only used for sampling directions, so they need no material.
+            None if is_light => Ok(Arc::new(Lambertian::new(&Color::new(0.0, 0.0, 0.0)))),
+            None => Err(self.error(entry.to_string(), "missing field \"material\"".to_string())),
+        }
+    }
+
+    fn object(
+        &self,
+        entry: &str,
+        desc: &ObjectDesc,
+        is_light: bool,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        let object: Arc<dyn Hittable> = match &desc.shape {
+            ShapeDesc::Sphere {
+                center,
+                radius,
+                center2,
+                material,
+            } => {
+                let mat = self.lookup(entry, material, is_light)?;
+                match center2 {
+                    Some(center2) => Arc::new(Sphere::new_move(
+                        vec3(*center),
+                        vec3(*center2),
+                        *radius,
+                        mat,
+                    )),
+                    None => Arc::new(Sphere::new(vec3(*center), *radius, mat)),
+                }
+            }
+            ShapeDesc::Quad { q, u, v, material } => Arc::new(Quad::new(
+                vec3(*q),
+                vec3(*u),
+                vec3(*v),
+                self.lookup(entry, material, is_light)?,
+            )),
+            ShapeDesc::Triangle { a, b, c, material } => {
+                let (a, b, c) = (vec3(*a), vec3(*b), vec3(*c));
+                let normal = unit_vector(&cross(&(b - a), &(c - a)));
+                Arc::new(Triangle::new_point(
+                    a,
+                    b,
+                    c,
+                    UV::default(),
+                    UV::new(1.0, 0.0),
+                    UV::new(0.0, 1.0),
+                    normal,
+                    normal,
+                    normal,
+                    self.lookup(entry, material, is_light)?,
+                ))
+            }
+            ShapeDesc::Box { a, b, material } => make_box(
+                &vec3(*a),
+                &vec3(*b),
+                self.lookup(entry, material, is_light)?,
+            ),
+            ShapeDesc::Model {
+                obj,
+                mtl,
+                obj2,
+                scale,
+                rotate_y,
+                translate,
+                material,
+            } => {
+                let mesh = Mesh::load(obj, mtl, obj2.as_deref())
+                    .map_err(|message| self.error(entry.to_string(), message))?;
+                let material = match material {
+                    Some(_) => Some(self.lookup(entry, material, is_light)?),
+                    None => None,
+                };
+                // The transforms of the entry go into the instance instead of wrapping it.
+                let matrix = Mat4::IDENTITY
+                    .scale(Vec3::new(*scale, *scale, *scale))
+                    .rotate_y(*rotate_y)
+                    .translate(vec3(*translate));
+                let matrix = transform_matrix(matrix, &desc.transform);
+                let instance = Instance::new(mesh, matrix, material)
+                    .map_err(|message| self.error(entry.to_string(), message))?;
+                Arc::new(instance)
+            }
+        };
+
+        let object: Arc<dyn Hittable> =
+            if desc.transform.is_empty() || matches!(desc.shape, ShapeDesc::Model { .. }) {
+                object
+            } else {
+                let matrix = transform_matrix(Mat4::IDENTITY, &desc.transform);
+                let transform = Transform::new(object, matrix)
+                    .map_err(|message| self.error(entry.to_string(), message))?;
+                Arc::new(transform)
+            };
+
+        if desc.motion.is_empty() {
+            return Ok(object);
+        }
+        // Directions towards lights are sampled without a ray time.
+        if is_light {
+            return Err(self.error(
+                entry.to_string(),
+                "light shapes cannot have a motion".to_string(),
+            ));
+        }
+        let keyframes = desc
+            .motion
+            .iter()
+            .map(|k| {
+                Keyframe::new(
+                    k.time,
+                    vec3(k.scale),
+                    vec3(k.axis),
+                    k.angle,
+                    vec3(k.translate),
+                )
+            })
+            .collect();
+        let motion = MotionTransform::new(object, keyframes)
+            .map_err(|message| self.error(entry.to_string(), message))?;
+        Ok(Arc::new(motion))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHTS_YAML: &str = "lights:\n  - { type: sphere, center: [0, 5, 0], radius: 1 }\n";
+    const LIGHTS_JSON: &str =
+        r#""lights": [{ "type": "sphere", "center": [0, 5, 0], "radius": 1 }]"#;
+
+    fn yaml(text: &str) -> Result<Scene, SceneError> {
+        parse_scene(Path::new("test.yaml"), &format!("{}{}", text, LIGHTS_YAML))
+    }
+
+    fn json(text: &str) -> Result<Scene, SceneError> {
+        parse_scene(
+            Path::new("test.json"),
+            &format!("{{ {}, {} }}", text, LIGHTS_JSON),
+        )
+    }
+
+    fn assert_error(result: Result<Scene, SceneError>, file: &str, entry: &str, message: &str) {
+        let Err(err) = result else {
+            panic!("the scene was accepted");
+        };
+        assert_eq!(err.file, Path::new(file));
+        assert_eq!(err.entry.as_deref(), Some(entry));
+        assert!(
+            err.message.contains(message),
+            "\"{}\" doesn't mention \"{}\"",
+            err.message,
+            message
+        );
+    }
+
+    #[test]
+    fn unknown_materials_name_the_entry() {
+        assert_error(
+            yaml(
+                "materials:\n  red: { type: lambertian, albedo: [1, 0, 0] }\n\
+                 objects:\n  - { type: sphere, center: [0, 0, 0], radius: 1, material: red }\n  \
+                 - { type: quad, q: [0, 0, 0], u: [1, 0, 0], v: [0, 1, 0], material: blue }\n",
+            ),
+            "test.yaml",
+            "objects[1]",
+            "unknown material \"blue\"",
+        );
+        assert_error(
+            json(
+                r#""objects": [{ "type": "box", "a": [0, 0, 0], "b": [1, 1, 1], "material": "x" }]"#,
+            ),
+            "test.json",
+            "objects[0]",
+            "unknown material \"x\"",
+        );
+    }
+
+    #[test]
+    fn unknown_fields_name_the_entry() {
+        assert_error(
+            yaml("objects:\n  - { type: sphere, center: [0, 0, 0], radius: 1, colour: red }\n"),
+            "test.yaml",
+            "objects[0]",
+            "unknown field `colour`",
+        );
+        assert_error(
+            json(
+                r#""materials": { "steel": { "type": "metal", "albedo": [1, 1, 1], "fuz": 0.1 } }"#,
+            ),
+            "test.json",
+            "materials.steel",
+            "unknown field `fuz`",
+        );
+        assert_error(
+            json(r#""camera": { "fov": 40 }"#),
+            "test.json",
+            "camera",
+            "unknown field `fov`",
+        );
+    }
+
+    #[test]
+    fn bad_types_name_the_entry() {
+        assert_error(
+            yaml(
+                "materials:\n  m: { type: lambertian }\n\
+                 objects:\n  - { type: sphere, center: [0, 0, 0], radius: 1, material: m }\n  \
+                 - { type: cone, radius: 1 }\n",
+            ),
+            "test.yaml",
+            "objects[1]",
+            "unknown variant `cone`",
+        );
+        assert_error(
+            json(r#""materials": { "shiny": { "type": "plastic" } }"#),
+            "test.json",
+            "materials.shiny",
+            "unknown variant `plastic`",
+        );
+        // Light shapes are checked the same way.
+        assert_error(
+            parse_scene(
+                Path::new("test.yaml"),
+                "lights:\n  - { type: sphere, center: [0, 5, 0], radius: 1 }\n  - { type: disk }\n",
+            ),
+            "test.yaml",
+            "lights[1]",
+            "unknown variant `disk`",
+        );
+    }
+
+    // Min and max corners of the box of a scene's objects.
+    fn corners(scene: &Scene) -> (Vec3, Vec3) {
+        let bbox = scene.world.bounding_box();
+        (
+            Vec3::new(bbox.x.min, bbox.y.min, bbox.z.min),
+            Vec3::new(bbox.x.max, bbox.y.max, bbox.z.max),
+        )
+    }
+
+    // Loose enough for the padding of the boxes of flat quads.
+    fn assert_close_vec(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn transforms_apply_in_the_listed_order() {
+        let unit_box = |transform: &str| {
+            let scene = yaml(&format!(
+                "materials:\n  m: {{ type: lambertian }}\nobjects:\n  \
+                 - {{ type: box, a: [0, 0, 0], b: [1, 1, 1], material: m, transform: {} }}\n",
+                transform
+            ))
+            .unwrap();
+            corners(&scene)
+        };
+        let center = |(min, max): (Vec3, Vec3)| (min + max) / 2.0;
+
+        // Scaled, then moved.
+        let (min, max) = unit_box("[{ scale: [2, 1, 1] }, { translate: [3, 0, 0] }]");
+        assert_close_vec(min, Vec3::new(3.0, 0.0, 0.0));
+        assert_close_vec(max, Vec3::new(5.0, 1.0, 1.0));
+        // Moved, then scaled along with the offset.
+        let (min, max) = unit_box("[{ translate: [3, 0, 0] }, { scale: [2, 1, 1] }]");
+        assert_close_vec(min, Vec3::new(6.0, 0.0, 0.0));
+        assert_close_vec(max, Vec3::new(8.0, 1.0, 1.0));
+        // Moved, then turned about the origin: the center at x = 3.5 ends up at z = -3.5.
+        assert_close_vec(
+            center(unit_box("[{ translate: [3, 0, 0] }, { rotate_y: 90 }]")),
+            Vec3::new(0.5, 0.5, -3.5),
+        );
+        // Turned in place, then moved.
+        assert_close_vec(
+            center(unit_box("[{ rotate_y: 90 }, { translate: [3, 0, 0] }]")),
+            Vec3::new(3.5, 0.5, -0.5),
+        );
+    }
+}